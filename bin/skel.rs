@@ -1,9 +1,19 @@
 use clap::{Parser, Subcommand};
 use miette::{IntoDiagnostic, Result};
 use std::env;
+use std::io::{self, IsTerminal};
 use std::path::PathBuf;
+use std::process;
 
 use skel::Skeleton;
+use skel::apply::{apply_plan, ApplyStatus};
+use skel::config::ProjectConfig;
+use skel::diff::{colorize, diff};
+use skel::history::undo;
+use skel::plan::Plan;
+use skel::runner::run;
+use skel::update::{update, UpdateStatus};
+use skel::verify::verify;
 use skel::util::normalize_path;
 
 #[derive(Parser)]
@@ -17,8 +27,27 @@ struct CliOptions {
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-    Apply,
-    Verify,
+    Apply {
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long)]
+        prune: bool,
+    },
+    Diff,
+    Plan,
+    Verify {
+        #[arg(long)]
+        diff: bool,
+    },
+    Update,
+    Undo {
+        name: Option<String>,
+    },
+    Run {
+        task: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
 }
 
 fn main() -> Result<()> {
@@ -39,8 +68,90 @@ fn main() -> Result<()> {
     }
     config_path = normalize_path(&current_dir, &config_path)?;
 
+    // undo only needs the project, so a skeleton that fails to load or
+    // can't be fetched doesn't get in the way of going back
+    if let Commands::Undo { name } = &cli.command {
+        let project = ProjectConfig::read_from(&config_path)?.root;
+        for result in undo(&project, name.as_deref())? {
+            println!("{:>8} {}", result.status, result.destination.display());
+        }
+        return Ok(());
+    }
+
     let skeleton = Skeleton::from_config_file(config_path)?;
-    println!("{:#?}", &skeleton);
+
+    match cli.command {
+        Commands::Apply { dry_run: true, prune } => {
+            print_plan(&Plan::from_skeleton_with_prune(&skeleton, prune)?);
+        },
+        Commands::Plan => {
+            print_plan(&Plan::from_skeleton(&skeleton)?);
+        },
+        Commands::Apply { dry_run: false, prune } => {
+            let plan = Plan::from_skeleton_with_prune(&skeleton, prune)?;
+            let results = apply_plan(&skeleton, plan)?;
+            for result in &results {
+                println!("{:>9} {}", result.status, result.destination.display());
+            }
+
+            if results.iter().any(|result| result.status == ApplyStatus::Orphaned) {
+                eprintln!("orphaned files are no longer in the skeleton, apply with --prune to delete them");
+            }
+            if results.iter().any(|result| result.status == ApplyStatus::Modified) {
+                eprintln!("modified files are no longer in the skeleton but were changed in the project, so they are kept");
+            }
+        },
+        Commands::Diff => {
+            print_diffs(&skeleton)?;
+        },
+        Commands::Verify { diff } => {
+            let drift = verify(&skeleton)?;
+            for entry in &drift {
+                println!("{:>11} {}", entry.kind, entry.destination.display());
+            }
+
+            if diff {
+                print_diffs(&skeleton)?;
+            }
+
+            if !drift.is_empty() {
+                process::exit(1);
+            }
+        },
+        Commands::Update => {
+            let results = update(&skeleton)?;
+            for result in &results {
+                println!("{:>9} {}", result.status, result.destination.display());
+            }
+
+            if results.iter().any(|result| result.status == UpdateStatus::Conflict) {
+                process::exit(1);
+            }
+        },
+        Commands::Undo { .. } => unreachable!("undo is handled before loading the skeleton"),
+        Commands::Run { task, args } => {
+            run(&skeleton, &task, &args)?;
+        },
+    };
+
+    Ok(())
+}
+
+fn print_plan(plan: &Plan) {
+    for step in &plan.steps {
+        println!("{:>9} {}", step.action, step.destination.display());
+    }
+}
+
+fn print_diffs(skeleton: &Skeleton) -> Result<()> {
+    let is_terminal = io::stdout().is_terminal();
+    for file_diff in diff(skeleton)? {
+        if is_terminal {
+            print!("{}", colorize(&file_diff));
+        } else {
+            print!("{}", file_diff);
+        }
+    }
 
     Ok(())
 }
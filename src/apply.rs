@@ -0,0 +1,376 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::config::{LockedSkeleton, Lockfile};
+use crate::content::{ContentKind, Policy};
+use crate::error::SkelError;
+use crate::history::commit_with_history;
+use crate::plan::{Action, Plan};
+use crate::skeleton::Skeleton;
+use crate::source;
+use crate::transaction::Transaction;
+use crate::update::{prune_bases, stage_bases};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ApplyResult {
+    pub destination: PathBuf,
+    pub status: ApplyStatus,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ApplyStatus {
+    Created,
+    Updated,
+    Unchanged,
+    Deleted,
+    Orphaned,
+    Modified,
+}
+
+impl fmt::Display for ApplyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            ApplyStatus::Created => "created",
+            ApplyStatus::Updated => "updated",
+            ApplyStatus::Unchanged => "unchanged",
+            ApplyStatus::Deleted => "deleted",
+            ApplyStatus::Orphaned => "orphaned",
+            ApplyStatus::Modified => "modified",
+        };
+
+        f.pad(status)
+    }
+}
+
+/// Writes every piece of content into the project, in dependency order.
+/// Files whose contents already match are left untouched, and so are files
+/// dropped from the skeleton. Afterwards the lockfile records what was
+/// applied.
+pub fn apply(skeleton: &Skeleton) -> Result<Vec<ApplyResult>, SkelError> {
+    let plan = Plan::from_skeleton(skeleton)?;
+    apply_plan(skeleton, plan)
+}
+
+/// Carries out a plan. Files the plan keeps although they were dropped from
+/// the skeleton are reported as orphaned, or as modified when the project
+/// changed them, and stay managed so the next apply still offers to delete
+/// them.
+///
+/// Every change, including the lockfile, is staged first and then moved
+/// into place together. If anything fails the project is left as it was.
+/// When something changes, what it replaced is kept in the history so the
+/// apply can be undone.
+pub fn apply_plan(skeleton: &Skeleton, plan: Plan) -> Result<Vec<ApplyResult>, SkelError> {
+    let previous = Lockfile::read_from(&skeleton.lockfile, &skeleton.project)?;
+    let mut lockfile = lock(skeleton, &plan, previous.as_ref());
+    let mut transaction = Transaction::new();
+    stage_bases(&skeleton.project, &mut transaction, &plan.steps)?;
+    let mut changed: Vec<PathBuf> = Vec::new();
+    let mut results: Vec<ApplyResult> = Vec::new();
+
+    for step in plan.steps {
+        let destination = skeleton.project.join(&step.destination);
+
+        let status = match step.action {
+            Action::Create => ApplyStatus::Created,
+            Action::Overwrite | Action::Chmod => ApplyStatus::Updated,
+            Action::Skip => ApplyStatus::Unchanged,
+            Action::Delete => ApplyStatus::Deleted,
+            Action::Orphan => ApplyStatus::Orphaned,
+            Action::Modified => ApplyStatus::Modified,
+        };
+
+        match status {
+            ApplyStatus::Created | ApplyStatus::Updated => {
+                match step.kind {
+                    ContentKind::Symlink => transaction.symlink(&destination, &String::from_utf8_lossy(&step.data))?,
+                    _ => transaction.write(&destination, &step.data, step.mode)?,
+                }
+                changed.push(step.destination.clone());
+            }
+            ApplyStatus::Deleted => {
+                transaction.delete(&destination);
+                lockfile.content.remove(&step.destination);
+                changed.push(step.destination.clone());
+            }
+            ApplyStatus::Unchanged | ApplyStatus::Orphaned | ApplyStatus::Modified => {}
+        };
+
+        results.push(ApplyResult {
+            destination: step.destination,
+            status,
+        });
+    }
+
+    transaction.write(&skeleton.lockfile, lockfile.to_kdl_string(&skeleton.project).as_bytes(), None)?;
+    if !changed.is_empty() {
+        if let Ok(lockfile) = skeleton.lockfile.strip_prefix(&skeleton.project) {
+            changed.push(lockfile.to_owned());
+        }
+    }
+    commit_with_history(&skeleton.project, transaction, &changed)?;
+    prune_bases(&skeleton.project, &lockfile)?;
+
+    Ok(results)
+}
+
+/// What a plan for the skeleton writes, to be recorded once it is written.
+/// Create-only content belongs to the project once written, so it isn't
+/// recorded as managed. Files planned for deletion keep the hash from the
+/// `previous` lockfile, which is what the skeleton last wrote there.
+pub(crate) fn lock(skeleton: &Skeleton, plan: &Plan, previous: Option<&Lockfile>) -> Lockfile {
+    Lockfile {
+        skeletons: skeleton
+            .sources
+            .iter()
+            .map(|resolved| LockedSkeleton {
+                source: resolved.source.clone(),
+                version: resolved.version.clone(),
+            })
+            .collect(),
+        features: skeleton.features.clone(),
+        variables: skeleton.variables.clone(),
+        content: plan
+            .steps
+            .iter()
+            .filter(|step| step.policy != Policy::CreateOnly)
+            .filter_map(|step| {
+                let hash = match step.action {
+                    Action::Delete | Action::Orphan | Action::Modified => {
+                        previous?.content.get(&step.destination)?.clone()
+                    }
+                    _ => source::hash(&step.data),
+                };
+                Some((step.destination.clone(), hash))
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::config::SkeletonSource;
+    use crate::plan::Step;
+    use crate::test_helpers::project_with_content;
+
+    #[test]
+    fn creates_missing_files() {
+        let project = project_with_content(&[("one.txt", "one"), ("nested/dot_two", "two")]);
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+
+        let results = apply(&skeleton).unwrap();
+        assert_eq!(results, vec![
+            ApplyResult { destination: PathBuf::from("one.txt"), status: ApplyStatus::Created },
+            ApplyResult { destination: PathBuf::from("nested/.two"), status: ApplyStatus::Created },
+        ]);
+
+        assert_eq!(fs::read_to_string(project.path().join("one.txt")).unwrap(), "one");
+        assert_eq!(fs::read_to_string(project.path().join("nested/.two")).unwrap(), "two");
+    }
+
+    #[test]
+    fn creates_symlinks() {
+        let project = project_with_content(&[("symlink_dot_clang-format", "../../.clang-format\n")]);
+        fs::write(project.path().join(".clang-format"), "stale").unwrap();
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+
+        let results = apply(&skeleton).unwrap();
+        assert_eq!(results, vec![
+            ApplyResult { destination: PathBuf::from(".clang-format"), status: ApplyStatus::Updated },
+        ]);
+        assert_eq!(
+            fs::read_link(project.path().join(".clang-format")).unwrap(),
+            PathBuf::from("../../.clang-format")
+        );
+
+        let results = apply(&skeleton).unwrap();
+        assert_eq!(results[0].status, ApplyStatus::Unchanged);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sets_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = project_with_content(&[("scripts/executable_build.sh", "build")]);
+        fs::create_dir(project.path().join("scripts")).unwrap();
+        fs::write(project.path().join("scripts/build.sh"), "build").unwrap();
+        fs::set_permissions(project.path().join("scripts/build.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+
+        let results = apply(&skeleton).unwrap();
+        assert_eq!(results, vec![
+            ApplyResult { destination: PathBuf::from("scripts/build.sh"), status: ApplyStatus::Updated },
+        ]);
+        let metadata = fs::metadata(project.path().join("scripts/build.sh")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+    }
+
+    #[test]
+    fn reports_updated_and_unchanged_files() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two")]);
+        fs::write(project.path().join("one.txt"), "one").unwrap();
+        fs::write(project.path().join("two.txt"), "changed").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let results = apply(&skeleton).unwrap();
+        assert_eq!(results, vec![
+            ApplyResult { destination: PathBuf::from("one.txt"), status: ApplyStatus::Unchanged },
+            ApplyResult { destination: PathBuf::from("two.txt"), status: ApplyStatus::Updated },
+        ]);
+
+        assert_eq!(fs::read_to_string(project.path().join("two.txt")).unwrap(), "two");
+    }
+
+    #[test]
+    fn writes_a_lockfile() {
+        let project = project_with_content(&[("one.txt", "{{ name }}")]);
+        fs::write(project.path().join(".skeleton/skeleton.kdl"), r#"
+            content "one.txt" {
+                kind "template"
+            }
+        "#).unwrap();
+        fs::write(project.path().join(".skeleton.kdl"), r#"
+            variables {
+                name "skel"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        apply(&skeleton).unwrap();
+
+        let lockfile_path = project.path().join(".skeleton.lock");
+        let lockfile = Lockfile::read_from(&lockfile_path, project.path()).unwrap().unwrap();
+        assert_eq!(lockfile.skeletons, vec![LockedSkeleton {
+            source: SkeletonSource {
+                path: project.path().join(".skeleton"),
+                ..Default::default()
+            },
+            version: None,
+        }]);
+        assert_eq!(lockfile.variables.get("name").unwrap(), "skel");
+        assert_eq!(lockfile.content.get(Path::new("one.txt")), Some(&source::hash(b"skel")));
+    }
+
+    #[test]
+    fn deletes_orphans_only_when_pruning() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two")]);
+        apply(&Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap()).unwrap();
+        fs::remove_file(project.path().join(".skeleton/content/two.txt")).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let results = apply(&skeleton).unwrap();
+        assert_eq!(results[1], ApplyResult { destination: PathBuf::from("two.txt"), status: ApplyStatus::Orphaned });
+        assert!(project.path().join("two.txt").exists());
+
+        // still managed, so pruning later removes it
+        let results = apply_plan(&skeleton, Plan::from_skeleton_with_prune(&skeleton, true).unwrap()).unwrap();
+        assert_eq!(results[1], ApplyResult { destination: PathBuf::from("two.txt"), status: ApplyStatus::Deleted });
+        assert!(!project.path().join("two.txt").exists());
+
+        let lockfile = Lockfile::read_from(&skeleton.lockfile, project.path()).unwrap().unwrap();
+        assert_eq!(lockfile.content.keys().collect::<Vec<_>>(), vec![Path::new("one.txt")]);
+    }
+
+    #[test]
+    fn keeps_orphans_the_project_changed() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two")]);
+        apply(&Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap()).unwrap();
+        fs::remove_file(project.path().join(".skeleton/content/two.txt")).unwrap();
+        fs::write(project.path().join("two.txt"), "changed").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let results = apply_plan(&skeleton, Plan::from_skeleton_with_prune(&skeleton, true).unwrap()).unwrap();
+        assert_eq!(results[1], ApplyResult { destination: PathBuf::from("two.txt"), status: ApplyStatus::Modified });
+        assert_eq!(fs::read_to_string(project.path().join("two.txt")).unwrap(), "changed");
+
+        // the lockfile keeps what the skeleton wrote, not the project's changes
+        let lockfile = Lockfile::read_from(&skeleton.lockfile, project.path()).unwrap().unwrap();
+        assert_eq!(lockfile.content.get(Path::new("two.txt")), Some(&source::hash(b"two")));
+    }
+
+    #[test]
+    fn does_what_the_plan_shows() {
+        for prune in [false, true] {
+            let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two"), ("three.txt", "three")]);
+            apply(&Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap()).unwrap();
+            fs::remove_file(project.path().join(".skeleton/content/two.txt")).unwrap();
+            fs::remove_file(project.path().join(".skeleton/content/three.txt")).unwrap();
+            fs::write(project.path().join("three.txt"), "changed").unwrap();
+
+            let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+            let plan = Plan::from_skeleton_with_prune(&skeleton, prune).unwrap();
+            let planned: Vec<(PathBuf, String)> = plan
+                .steps
+                .iter()
+                .map(|step| (step.destination.clone(), step.action.to_string()))
+                .collect();
+
+            let results: Vec<(PathBuf, String)> = apply_plan(&skeleton, plan)
+                .unwrap()
+                .into_iter()
+                .map(|result| (result.destination, result.status.to_string()))
+                .collect();
+            let two = if prune { ("delete", "deleted") } else { ("orphan", "orphaned") };
+            assert_eq!(planned, vec![
+                (PathBuf::from("one.txt"), "skip".to_owned()),
+                (PathBuf::from("three.txt"), "modified".to_owned()),
+                (PathBuf::from("two.txt"), two.0.to_owned()),
+            ]);
+            assert_eq!(results, vec![
+                (PathBuf::from("one.txt"), "unchanged".to_owned()),
+                (PathBuf::from("three.txt"), "modified".to_owned()),
+                (PathBuf::from("two.txt"), two.1.to_owned()),
+            ]);
+            assert_eq!(project.path().join("two.txt").exists(), !prune);
+        }
+    }
+
+    #[test]
+    fn keeps_create_only_content_when_dropped() {
+        let project = project_with_content(&[("README.md", "readme")]);
+        fs::write(project.path().join(".skeleton/skeleton.kdl"), r#"
+            content "README.md" {
+                policy "create_only"
+            }
+        "#).unwrap();
+        apply(&Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap()).unwrap();
+        fs::remove_file(project.path().join(".skeleton/content/README.md")).unwrap();
+        fs::remove_file(project.path().join(".skeleton/skeleton.kdl")).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        assert_eq!(apply_plan(&skeleton, Plan::from_skeleton_with_prune(&skeleton, true).unwrap()).unwrap(), vec![]);
+        assert!(project.path().join("README.md").exists());
+    }
+
+    #[test]
+    fn leaves_the_project_untouched_on_failure() {
+        let project = project_with_content(&[("one.txt", "one"), ("two/three.txt", "three")]);
+        fs::write(project.path().join("one.txt"), "changed").unwrap();
+        // two/ can't be created while a file is in the way
+        fs::write(project.path().join("two"), "").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan {
+            steps: vec![
+                Step { action: Action::Overwrite, destination: PathBuf::from("one.txt"), data: b"one".to_vec(), kind: ContentKind::File, mode: None, policy: Policy::Overwrite },
+                Step { action: Action::Create, destination: PathBuf::from("two/three.txt"), data: b"three".to_vec(), kind: ContentKind::File, mode: None, policy: Policy::Overwrite },
+            ],
+        };
+
+        assert!(apply_plan(&skeleton, plan).is_err());
+        assert_eq!(fs::read_to_string(project.path().join("one.txt")).unwrap(), "changed");
+        assert!(!project.path().join(".skeleton.lock").exists());
+
+        let mut files: Vec<String> = fs::read_dir(project.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![".skeleton", "one.txt", "two"]);
+    }
+}
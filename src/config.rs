@@ -1,8 +1,11 @@
 mod fs_helpers;
 mod kdl_helpers;
 
+pub mod lockfile;
+pub use lockfile::{LockedSkeleton, Lockfile};
+
 pub mod project;
-pub use project::ProjectConfig;
+pub use project::{ProjectConfig, SkeletonSource};
 
 pub mod skeleton;
 pub use skeleton::SkeletonConfig;
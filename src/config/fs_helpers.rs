@@ -74,6 +74,7 @@ pub fn read_tree(dir: &PathBuf, root: &PathBuf) -> Result<Vec<PathBuf>, SkelErro
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::match_like_matches_macro)]
 mod tests {
     use super::*;
 
@@ -1,5 +1,5 @@
-use kdl::{KdlDocument, KdlEntry, KdlValue};
-use tera::{Context, Number, Value};
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue};
+use tera::{Context, Map, Number, Value};
 
 use crate::error::{ConfigError, SkelError};
 
@@ -11,13 +11,12 @@ pub fn first_string_arg<F>(
 where
     F: FnOnce() -> Result<String, SkelError>,
 {
-    let node = document.get(name);
-    if node.is_some() {
-        match node.unwrap().get(0) {
+    if let Some(node) = document.get(name) {
+        match node.get(0) {
             Some(entry) => match entry.value().as_string() {
                 Some(value) => Ok(value.to_owned()),
                 None => Err(
-                    ConfigError::from_invalid_string_argument(document, node.unwrap(), 0).into(),
+                    ConfigError::from_invalid_string_argument(document, node, 0).into(),
                 ),
             },
             None => Err(ConfigError::from_missing_argument(document, name).into()),
@@ -27,6 +26,22 @@ where
     }
 }
 
+/// Reads the first argument of a node nested in `siblings` as a string.
+/// Spans in errors point into `document`, the config the node came from.
+pub fn string_arg(
+    document: &KdlDocument,
+    siblings: &KdlDocument,
+    node: &KdlNode,
+) -> Result<String, ConfigError> {
+    match node.get(0) {
+        Some(entry) => match entry.value().as_string() {
+            Some(value) => Ok(value.to_owned()),
+            None => Err(ConfigError::from_invalid_string_argument(document, node, 0)),
+        },
+        None => Err(ConfigError::from_missing_argument(siblings, node.name().value())),
+    }
+}
+
 pub fn kdl_entry_to_tera_value(entry: &KdlEntry) -> Value {
     match entry.value().to_owned() {
         KdlValue::RawString(s) | KdlValue::String(s) => Value::String(s.to_owned()),
@@ -64,16 +79,118 @@ pub fn variables_from_kdl_document(doc: &KdlDocument) -> Result<Context, SkelErr
     let children = children_opt.unwrap();
     for node in children.nodes() {
         let name = node.name().value().to_owned();
-        let entry_opt = node.get(0);
-        if entry_opt.is_none() {
-            return Err(ConfigError::from_missing_argument(children, &name).into());
+        variables.insert(name, &kdl_node_to_tera_value(children, node)?);
+    }
+
+    Ok(variables)
+}
+
+/// Reads the arguments of every `features` node, without duplicates.
+pub fn features_from_kdl_document(document: &KdlDocument) -> Result<Vec<String>, ConfigError> {
+    let mut features: Vec<String> = Vec::new();
+    for node in document.nodes() {
+        if node.name().value() != "features" {
+            continue;
         }
 
-        let entry = entry_opt.unwrap();
-        variables.insert(name, &kdl_entry_to_tera_value(entry));
+        let mut index = 0;
+        for entry in node.entries() {
+            if entry.name().is_some() {
+                continue;
+            }
+
+            let feature = match entry.value().as_string() {
+                Some(value) => Ok(value.to_owned()),
+                None => Err(ConfigError::from_invalid_string_argument(
+                    document, node, index,
+                )),
+            }?;
+            index += 1;
+
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
     }
 
-    Ok(variables)
+    Ok(features)
+}
+
+/// Converts a variable node into a value. The first argument is a plain
+/// value and properties without arguments are a map. Lists are written as
+/// children named "-", or as a `(list)` node when empty; any other children
+/// are a map.
+pub fn kdl_node_to_tera_value(siblings: &KdlDocument, node: &KdlNode) -> Result<Value, ConfigError> {
+    let is_list = node.ty().map(|ty| ty.value() == "list").unwrap_or(false);
+    if is_list && node.children().map(|children| children.nodes().is_empty()).unwrap_or(true) {
+        return Ok(Value::Array(vec![]));
+    }
+
+    if let Some(children) = node.children() {
+        let nodes = children.nodes();
+        if !nodes.is_empty() && nodes.iter().all(|child| child.name().value() == "-") {
+            let items = children
+                .nodes()
+                .iter()
+                .map(|child| kdl_node_to_tera_value(children, child))
+                .collect::<Result<Vec<Value>, ConfigError>>()?;
+            return Ok(Value::Array(items));
+        }
+
+        let mut map = Map::new();
+        for child in children.nodes() {
+            map.insert(child.name().value().to_owned(), kdl_node_to_tera_value(children, child)?);
+        }
+        return Ok(Value::Object(map));
+    }
+
+    if let Some(entry) = node.get(0) {
+        return Ok(kdl_entry_to_tera_value(entry));
+    }
+
+    let mut props = Map::new();
+    for entry in node.entries() {
+        if let Some(name) = entry.name() {
+            props.insert(name.value().to_owned(), kdl_entry_to_tera_value(entry));
+        }
+    }
+
+    if props.is_empty() {
+        return Err(ConfigError::from_missing_argument(siblings, node.name().value()));
+    }
+    Ok(Value::Object(props))
+}
+
+/// The inverse of `kdl_node_to_tera_value`. Lists are always written as
+/// "-" children so a list with a single item reads back as a list, and
+/// empty lists carry a `(list)` annotation to tell them apart from maps.
+pub fn tera_value_to_kdl_node(name: &str, value: &Value) -> KdlNode {
+    let mut node = KdlNode::new(name);
+
+    match value {
+        Value::Null => node.push(KdlValue::Null),
+        Value::Bool(b) => node.push(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => node.push(i),
+            None => node.push(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => node.push(s.as_str()),
+        Value::Array(items) if items.is_empty() => node.set_ty("list"),
+        Value::Array(items) => {
+            let children = node.ensure_children();
+            for item in items {
+                children.nodes_mut().push(tera_value_to_kdl_node("-", item));
+            }
+        }
+        Value::Object(map) => {
+            let children = node.ensure_children();
+            for (key, item) in map {
+                children.nodes_mut().push(tera_value_to_kdl_node(key, item));
+            }
+        }
+    };
+
+    node
 }
 
 #[cfg(test)]
@@ -129,4 +246,122 @@ mod tests {
             assert!(is_invalid_string_error);
         }
     }
+
+    mod string_arg_helper {
+        use super::*;
+        use crate::error::ConfigErrorKind;
+
+        #[test]
+        fn returns_the_first_arg() {
+            let doc: KdlDocument = "content \"one\" { kind \"template\"; }".parse().unwrap();
+            let children = doc.nodes()[0].children().unwrap();
+            let result = string_arg(&doc, children, &children.nodes()[0]);
+            assert_eq!(result.unwrap(), "template".to_owned());
+        }
+
+        #[test]
+        fn errors_for_missing_argument() {
+            let doc: KdlDocument = "content \"one\" { kind; }".parse().unwrap();
+            let children = doc.nodes()[0].children().unwrap();
+            let result = string_arg(&doc, children, &children.nodes()[0]);
+            assert_eq!(result.unwrap_err().kind, ConfigErrorKind::MissingArgument);
+        }
+
+        #[test]
+        fn errors_for_non_string_argument() {
+            let doc: KdlDocument = "content \"one\" { kind 1; }".parse().unwrap();
+            let children = doc.nodes()[0].children().unwrap();
+            let result = string_arg(&doc, children, &children.nodes()[0]);
+            assert_eq!(result.unwrap_err().kind, ConfigErrorKind::InvalidString);
+        }
+    }
+
+    mod variables_from_kdl_document_helper {
+        use super::*;
+        use tera::to_value;
+
+        #[test]
+        fn reads_lists_and_maps() {
+            let doc: KdlDocument = r#"
+                variables {
+                    single "one"
+                    several "one" "two"
+                    list {
+                        - "one"
+                        - "two"
+                    }
+                    (list)none
+                    empty {}
+                    map name="api" port=80
+                    services {
+                        - name="api"
+                        - "worker"
+                    }
+                    nested {
+                        host "localhost"
+                        ports {
+                            - 80
+                            - 443
+                        }
+                    }
+                }
+            "#.parse().unwrap();
+
+            let variables = variables_from_kdl_document(&doc).unwrap();
+            assert_eq!(variables.get("single"), Some(&to_value("one").unwrap()));
+            assert_eq!(variables.get("several"), Some(&to_value("one").unwrap()));
+            assert_eq!(variables.get("list"), Some(&to_value(["one", "two"]).unwrap()));
+            assert_eq!(variables.get("none"), Some(&Value::Array(vec![])));
+            assert_eq!(variables.get("empty"), Some(&Value::Object(Map::new())));
+
+            let mut map = Map::new();
+            map.insert("name".to_owned(), to_value("api").unwrap());
+            map.insert("port".to_owned(), to_value(80).unwrap());
+            assert_eq!(variables.get("map"), Some(&Value::Object(map.clone())));
+
+            map.remove("port");
+            assert_eq!(variables.get("services"), Some(&Value::Array(vec![
+                Value::Object(map),
+                to_value("worker").unwrap(),
+            ])));
+
+            let mut nested = Map::new();
+            nested.insert("host".to_owned(), to_value("localhost").unwrap());
+            nested.insert("ports".to_owned(), to_value([80, 443]).unwrap());
+            assert_eq!(variables.get("nested"), Some(&Value::Object(nested)));
+        }
+    }
+
+    mod tera_value_to_kdl_node_helper {
+        use super::*;
+        use tera::to_value;
+
+        #[test]
+        fn reads_back_the_same_value() {
+            let value = to_value(nested_value()).unwrap();
+            let mut doc = KdlDocument::new();
+            doc.nodes_mut().push(tera_value_to_kdl_node("value", &value));
+
+            let reparsed: KdlDocument = doc.to_string().parse().unwrap();
+            let result = kdl_node_to_tera_value(&reparsed, reparsed.get("value").unwrap()).unwrap();
+            assert_eq!(result, value);
+        }
+
+        fn nested_value() -> Map<String, Value> {
+            let mut service = Map::new();
+            service.insert("name".to_owned(), to_value("api").unwrap());
+            service.insert("port".to_owned(), to_value(80).unwrap());
+            service.insert("ratio".to_owned(), to_value(0.5).unwrap());
+
+            let mut map = Map::new();
+            map.insert("services".to_owned(), Value::Array(vec![Value::Object(service)]));
+            map.insert("single".to_owned(), to_value(["one"]).unwrap());
+            map.insert("enabled".to_owned(), Value::Bool(true));
+            map.insert("nothing".to_owned(), Value::Null);
+            map.insert("no_items".to_owned(), Value::Array(vec![]));
+            map.insert("no_keys".to_owned(), Value::Object(Map::new()));
+            map.insert("with spaces".to_owned(), to_value("\"quoted\"").unwrap());
+            map
+        }
+    }
 }
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use kdl::{KdlDocument, KdlEntry, KdlNode};
+use tera::{Context, Value};
+
+use crate::config::fs_helpers::read_to_string_with_default;
+use crate::config::kdl_helpers::{features_from_kdl_document, string_arg, tera_value_to_kdl_node, variables_from_kdl_document};
+use crate::config::project::SkeletonSource;
+use crate::error::{ConfigError, SkelError};
+
+const HEADER: &str = "// Written by skel apply. Do not edit by hand.\n";
+
+/// What the last apply was made from and what it wrote: the skeletons at
+/// the version they were resolved to, the enabled features, the final
+/// variables, and a hash of every destination as it was written.
+#[derive(Debug, Default)]
+pub struct Lockfile {
+    pub skeletons: Vec<LockedSkeleton>,
+    pub features: Vec<String>,
+    pub variables: Context,
+    pub content: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LockedSkeleton {
+    pub source: SkeletonSource,
+    pub version: Option<String>,
+}
+
+impl Lockfile {
+    /// Reads the lockfile at `path`, or `None` if the project was never
+    /// applied. Relative skeleton locations are relative to `root`.
+    pub fn read_from(path: &PathBuf, root: &Path) -> Result<Option<Self>, SkelError> {
+        let (lockfile_content, is_default) = read_to_string_with_default(path)?;
+        if is_default {
+            return Ok(None);
+        }
+
+        let document: KdlDocument = lockfile_content.parse()?;
+        let mut lockfile = Lockfile {
+            features: features_from_kdl_document(&document)?,
+            variables: variables_from_kdl_document(&document)?,
+            ..Default::default()
+        };
+
+        for node in document.nodes() {
+            match node.name().value() {
+                "skeleton" => {
+                    let source = SkeletonSource::from_kdl_node(&document, root, node)?;
+                    let version = match node.children() {
+                        Some(children) => match children.get("version") {
+                            Some(version) => Some(string_arg(&document, children, version)?),
+                            None => None,
+                        },
+                        None => None,
+                    };
+
+                    lockfile.skeletons.push(LockedSkeleton { source, version });
+                }
+                "content" => {
+                    let destination = string_arg(&document, &document, node)?;
+                    let hash = match node.get("hash") {
+                        Some(entry) => match entry.value().as_string() {
+                            Some(hash) => hash.to_owned(),
+                            None => return Err(ConfigError::from_invalid_string_argument(&document, node, "hash").into()),
+                        },
+                        None => return Err(ConfigError::from_missing_argument(&document, "content").into()),
+                    };
+
+                    lockfile.content.insert(PathBuf::from(destination), hash);
+                }
+                _ => {}
+            };
+        }
+
+        Ok(Some(lockfile))
+    }
+
+    pub fn write_to(&self, path: &PathBuf, root: &Path) -> Result<(), SkelError> {
+        fs::write(path, self.to_kdl_string(root))?;
+
+        Ok(())
+    }
+
+    /// The lockfile as written to disk, with skeleton locations inside
+    /// `root` relative to it.
+    pub fn to_kdl_string(&self, root: &Path) -> String {
+        let mut document = KdlDocument::new();
+
+        for skeleton in &self.skeletons {
+            let mut node = KdlNode::new("skeleton");
+            node.push(skeleton.source.location(root));
+
+            let mut children = KdlDocument::new();
+            if skeleton.source.mount != Path::new("") {
+                children.nodes_mut().push(string_node("mount", &skeleton.source.mount.to_string_lossy()));
+            }
+            if let Some(reference) = &skeleton.source.reference {
+                children.nodes_mut().push(string_node("ref", reference));
+            }
+            if let Some(version) = &skeleton.version {
+                children.nodes_mut().push(string_node("version", version));
+            }
+            if !children.nodes().is_empty() {
+                node.set_children(children);
+            }
+
+            document.nodes_mut().push(node);
+        }
+
+        if !self.features.is_empty() {
+            let mut node = KdlNode::new("features");
+            for feature in &self.features {
+                node.push(feature.as_str());
+            }
+
+            document.nodes_mut().push(node);
+        }
+
+        if let Value::Object(variables) = self.variables.clone().into_json() {
+            let mut node = KdlNode::new("variables");
+            let children = node.ensure_children();
+            for (name, value) in variables {
+                children.nodes_mut().push(tera_value_to_kdl_node(&name, &value));
+            }
+
+            document.nodes_mut().push(node);
+        }
+
+        for (destination, hash) in &self.content {
+            let mut node = string_node("content", &destination.to_string_lossy());
+            node.push(KdlEntry::new_prop("hash", hash.as_str()));
+            document.nodes_mut().push(node);
+        }
+
+        document.fmt();
+        format!("{}{}", HEADER, document)
+    }
+}
+
+fn string_node(name: &str, value: &str) -> KdlNode {
+    let mut node = KdlNode::new(name);
+    node.push(value);
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn returns_none_when_missing() {
+        let dir = TempDir::new().unwrap();
+        let lockfile = Lockfile::read_from(&dir.path().join(".skeleton.lock"), dir.path()).unwrap();
+        assert!(lockfile.is_none());
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".skeleton.lock");
+
+        let mut variables = Context::new();
+        variables.insert("name", "skel");
+        variables.insert("services", &vec!["api"]);
+
+        let mut lockfile = Lockfile {
+            skeletons: vec![
+                LockedSkeleton {
+                    source: SkeletonSource {
+                        path: dir.path().join(".skeleton"),
+                        ..Default::default()
+                    },
+                    version: None,
+                },
+                LockedSkeleton {
+                    source: SkeletonSource {
+                        mount: PathBuf::from("backend"),
+                        repository: Some("https://example.com/skeleton.git".to_owned()),
+                        reference: Some("v1".to_owned()),
+                        ..Default::default()
+                    },
+                    version: Some("0123abcd".to_owned()),
+                },
+                LockedSkeleton {
+                    source: SkeletonSource {
+                        archive: Some(dir.path().join("dist/skeleton.zip")),
+                        ..Default::default()
+                    },
+                    version: Some("4567ef".to_owned()),
+                },
+            ],
+            features: vec!["docker".to_owned(), "ci".to_owned()],
+            variables,
+            ..Default::default()
+        };
+        lockfile.content.insert(PathBuf::from("README.md"), "89ab".to_owned());
+        lockfile.content.insert(PathBuf::from("backend/Cargo.toml"), "cdef".to_owned());
+        lockfile.write_to(&path, dir.path()).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with(HEADER));
+        // locations inside the project are kept relative
+        assert!(written.contains("skeleton \".skeleton\""));
+        assert!(written.contains("skeleton \"dist/skeleton.zip\""));
+
+        let read = Lockfile::read_from(&path, dir.path()).unwrap().unwrap();
+        assert_eq!(read.skeletons, lockfile.skeletons);
+        assert_eq!(read.features, lockfile.features);
+        assert_eq!(read.variables, lockfile.variables);
+        assert_eq!(read.content, lockfile.content);
+    }
+}
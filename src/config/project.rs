@@ -1,23 +1,105 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use kdl::KdlDocument;
+use kdl::{KdlDocument, KdlNode};
 use tera::Context;
 
 use crate::config::fs_helpers::read_to_string_with_default;
-use crate::config::kdl_helpers::{first_string_arg, variables_from_kdl_document};
-use crate::config::task::Task;
+use crate::config::kdl_helpers::{features_from_kdl_document, first_string_arg, string_arg, variables_from_kdl_document};
+use crate::config::task::{tasks_from_kdl_document, Task};
 use crate::error::{ConfigError, SkelError};
+use crate::util::normalize_path;
 
 #[derive(Debug, Default)]
 pub struct ProjectConfig {
     pub root: PathBuf,
-    pub skeleton: PathBuf,
+    pub skeletons: Vec<SkeletonSource>,
     pub variables: Context,
     pub tasks: HashMap<String, Task>,
+    pub features: Vec<String>,
     pub is_default: bool,
 }
 
+/// A skeleton applied to the project, with its content placed under `mount`
+/// relative to the project root. Skeletons kept in git have a `repository`
+/// and are checked out at `reference` before being read from `path`, and
+/// packaged skeletons have an `archive` that is extracted first.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SkeletonSource {
+    pub path: PathBuf,
+    pub mount: PathBuf,
+    pub repository: Option<String>,
+    pub reference: Option<String>,
+    pub archive: Option<PathBuf>,
+}
+
+impl SkeletonSource {
+    /// Reads a `skeleton` node. The argument is a git URL, a path to a git
+    /// repository or archive, or a skeleton directory, relative to `root`.
+    pub(crate) fn from_kdl_node(document: &KdlDocument, root: &Path, node: &KdlNode) -> Result<Self, SkelError> {
+        let skeleton_str = string_arg(document, document, node)?;
+        let mut source = SkeletonSource::default();
+        let mut ref_node = None;
+
+        if let Some(children) = node.children() {
+            for child in children.nodes() {
+                match child.name().value() {
+                    "mount" => {
+                        source.mount = PathBuf::from(string_arg(document, children, child)?);
+                    }
+                    "ref" => {
+                        source.reference = Some(string_arg(document, children, child)?);
+                        ref_node = Some(child);
+                    }
+                    _ => {}
+                };
+            }
+        }
+
+        if let Some(ref_node) = ref_node {
+            if is_archive(Path::new(&skeleton_str)) {
+                return Err(ConfigError::from_archive_ref(document, ref_node).into());
+            }
+        }
+
+        if is_remote(&skeleton_str) {
+            source.repository = Some(skeleton_str);
+        } else {
+            let path = normalize_path(root, &PathBuf::from(skeleton_str))?;
+            if source.reference.is_some() || path.extension().is_some_and(|ext| ext == "git") {
+                source.repository = Some(path.to_string_lossy().into_owned());
+            } else if is_archive(&path) {
+                source.archive = Some(path);
+            } else {
+                source.path = path;
+            }
+        }
+
+        Ok(source)
+    }
+
+    /// The argument of the `skeleton` node this source was read from, with
+    /// paths inside `root` made relative to it again.
+    pub fn location(&self, root: &Path) -> String {
+        let path = match (&self.repository, &self.archive) {
+            (Some(repository), _) => Path::new(repository),
+            (None, Some(archive)) => archive.as_path(),
+            (None, None) => self.path.as_path(),
+        };
+
+        path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned()
+    }
+}
+
+fn is_remote(location: &str) -> bool {
+    location.contains("://") || location.starts_with("git@")
+}
+
+fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz") || name.ends_with(".zip")
+}
+
 impl ProjectConfig {
     pub fn read_from(path: &PathBuf) -> Result<Self, SkelError> {
         let (config_content, is_default) = read_to_string_with_default(path)?;
@@ -27,45 +109,41 @@ impl ProjectConfig {
         let root_str = first_string_arg(&document, "root", default_root)?;
         let root = PathBuf::from(root_str);
 
-        let default_skeleton = || Ok(root.join(".skeleton").to_string_lossy().into_owned());
-        let skeleton_str = first_string_arg(&document, "skeleton", default_skeleton)?;
-        let skeleton = PathBuf::from(skeleton_str);
-
-        let variables = variables_from_kdl_document(&document)?;
-
-        let mut tasks: HashMap<String, Task> = HashMap::new();
+        let mut skeletons: Vec<SkeletonSource> = Vec::new();
         for node in document.nodes() {
-            if node.name().value() != "task" {
+            if node.name().value() != "skeleton" {
                 continue;
             }
 
-            let name = match node.get(0) {
-                Some(name) => match name.value().as_string() {
-                    Some(value) => Ok(value.to_owned()),
-                    None => Err(ConfigError::from_invalid_string_argument(
-                        &document, node, 0,
-                    )),
-                },
-                None => Err(ConfigError::from_missing_argument(&document, "task")),
-            }?;
+            skeletons.push(SkeletonSource::from_kdl_node(&document, &root, node)?);
+        }
 
-            if let Some(children) = node.children() {
-                let task = Task::from_kdl_document(children, name.to_owned())?;
-                tasks.insert(name, task);
-            }
+        if skeletons.is_empty() {
+            skeletons.push(SkeletonSource {
+                path: root.join(".skeleton"),
+                ..Default::default()
+            });
         }
 
+        let variables = variables_from_kdl_document(&document)?;
+
+        let tasks = tasks_from_kdl_document(&document, &document)?;
+
+        let features = features_from_kdl_document(&document)?;
+
         Ok(Self {
             root,
-            skeleton,
+            skeletons,
             variables,
             tasks,
+            features,
             is_default,
         })
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::match_like_matches_macro)]
 mod tests {
     use super::*;
 
@@ -117,10 +195,14 @@ mod tests {
             assert_eq!(config.root, PathBuf::from("/A/PATH/THAT/DOES/NOT"));
             // parent of the config path + .skeleton
             assert_eq!(
-                config.skeleton,
-                PathBuf::from("/A/PATH/THAT/DOES/NOT/.skeleton")
+                config.skeletons,
+                vec![SkeletonSource {
+                    path: PathBuf::from("/A/PATH/THAT/DOES/NOT/.skeleton"),
+                    ..Default::default()
+                }]
             );
             assert_eq!(config.variables, Context::new());
+            assert_eq!(config.features, Vec::<String>::new());
         }
 
         #[test]
@@ -149,7 +231,13 @@ mod tests {
             let config = result.unwrap();
             assert_eq!(config.is_default, false);
             assert_eq!(config.root, PathBuf::from("/"));
-            assert_eq!(config.skeleton, PathBuf::from("/etc/skeleton"));
+            assert_eq!(
+                config.skeletons,
+                vec![SkeletonSource {
+                    path: PathBuf::from("/etc/skeleton"),
+                    ..Default::default()
+                }]
+            );
 
             let mut expected_context = Context::new();
             expected_context.insert("foo".to_owned(), &Value::String("bar".into()));
@@ -175,6 +263,128 @@ mod tests {
             });
         }
 
+        #[test]
+        fn reads_mounted_skeletons() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"root "/project"
+            skeleton "skeletons/web" {{
+                mount "frontend"
+            }}
+            skeleton "/skeletons/rust" {{
+                mount "backend"
+            }}"#).unwrap();
+
+            let config = ProjectConfig::read_from(&file.path().to_path_buf()).unwrap();
+            assert_eq!(config.skeletons, vec![
+                SkeletonSource {
+                    path: PathBuf::from("/project/skeletons/web"),
+                    mount: PathBuf::from("frontend"),
+                    ..Default::default()
+                },
+                SkeletonSource {
+                    path: PathBuf::from("/skeletons/rust"),
+                    mount: PathBuf::from("backend"),
+                    ..Default::default()
+                },
+            ]);
+        }
+
+        #[test]
+        fn reads_git_skeletons() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"root "/project"
+            skeleton "https://example.com/skeleton.git" {{
+                ref "v1.2.0"
+            }}
+            skeleton "../skeleton" {{
+                ref "main"
+            }}
+            skeleton "../bare.git""#).unwrap();
+
+            let config = ProjectConfig::read_from(&file.path().to_path_buf()).unwrap();
+            assert_eq!(config.skeletons, vec![
+                SkeletonSource {
+                    repository: Some("https://example.com/skeleton.git".to_owned()),
+                    reference: Some("v1.2.0".to_owned()),
+                    ..Default::default()
+                },
+                SkeletonSource {
+                    repository: Some("/skeleton".to_owned()),
+                    reference: Some("main".to_owned()),
+                    ..Default::default()
+                },
+                SkeletonSource {
+                    repository: Some("/bare.git".to_owned()),
+                    ..Default::default()
+                },
+            ]);
+        }
+
+        #[test]
+        fn reads_archive_skeletons() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"root "/project"
+            skeleton "dist/skeleton.tar.gz"
+            skeleton "dist/skeleton.tgz"
+            skeleton "dist/skeleton.zip""#).unwrap();
+
+            let config = ProjectConfig::read_from(&file.path().to_path_buf()).unwrap();
+            let archives: Vec<Option<PathBuf>> = config.skeletons.into_iter().map(|s| s.archive).collect();
+            assert_eq!(archives, vec![
+                Some(PathBuf::from("/project/dist/skeleton.tar.gz")),
+                Some(PathBuf::from("/project/dist/skeleton.tgz")),
+                Some(PathBuf::from("/project/dist/skeleton.zip")),
+            ]);
+        }
+
+        #[test]
+        fn errors_when_archives_have_a_ref() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"skeleton "dist/skeleton.tar.gz" {{
+                ref "main"
+            }}"#).unwrap();
+
+            let result = ProjectConfig::read_from(&file.path().to_path_buf());
+            let is_archive_ref_error = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err.kind == ConfigErrorKind::ArchiveRef,
+                _ => false,
+            };
+            assert!(is_archive_ref_error);
+        }
+
+        #[test]
+        fn reads_features() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"features "docker" "ci"
+            features "bench" "docker""#).unwrap();
+
+            let config = ProjectConfig::read_from(&file.path().to_path_buf()).unwrap();
+            assert_eq!(config.features, vec!["docker", "ci", "bench"]);
+        }
+
+        #[test]
+        fn errors_when_feature_is_not_a_string() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(file, r#"features "docker" 1"#).unwrap();
+
+            let result = ProjectConfig::read_from(&file.path().to_path_buf());
+            let is_invalid_string_error = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err.kind == ConfigErrorKind::InvalidString,
+                _ => false,
+            };
+            assert!(is_invalid_string_error);
+        }
+
         #[test]
         fn errors_when_variable_is_missing_value() {
             let mut file = NamedTempFile::new().unwrap();
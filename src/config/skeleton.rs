@@ -1,16 +1,18 @@
 use core::cmp::Ordering;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 use feruca::Collator;
-use kdl::KdlDocument;
+use globset::GlobBuilder;
+use kdl::{KdlDocument, KdlEntry, KdlNode};
 use tera::Context;
 
 use crate::config::fs_helpers;
 use crate::config::kdl_helpers;
-use crate::config::task::Task;
-use crate::content::Content;
+use crate::config::task::{self, Task};
+use crate::content::{parse_mode, Content, ContentKind, ForEach, Policy};
 use crate::error::{ConfigError, SkelError};
+use crate::util::normalize_path;
 
 #[derive(Debug, Default)]
 pub struct SkeletonConfig {
@@ -18,39 +20,68 @@ pub struct SkeletonConfig {
     pub content: HashMap<String, Content>,
     pub tasks: HashMap<String, Task>,
     pub variables: Context,
+    pub features: HashMap<String, Feature>,
     pub is_default: bool,
 }
 
+/// Optional content, variables and tasks that are only merged into a
+/// skeleton when a project enables the feature.
+#[derive(Debug, Default)]
+pub struct Feature {
+    pub name: String,
+    pub content: HashMap<String, Content>,
+    pub variables: Context,
+    pub tasks: HashMap<String, Task>,
+}
+
 impl SkeletonConfig {
     pub fn read_from(path: &PathBuf) -> Result<Self, SkelError> {
+        let mut chain: Vec<PathBuf> = Vec::new();
+        Self::read_extending(path, &mut chain)
+    }
+
+    /// Reads a skeleton on top of the one it extends, if any. `chain` holds
+    /// the directories of the skeletons currently being read.
+    fn read_extending(path: &PathBuf, chain: &mut Vec<PathBuf>) -> Result<Self, SkelError> {
         let (config_content, is_default) = fs_helpers::read_to_string_with_default(path)?;
         let document: KdlDocument = config_content.parse()?;
 
-        let root: PathBuf = path.parent().unwrap().join("content");
+        let skeleton_dir = path.parent().unwrap().to_path_buf();
+        let root: PathBuf = skeleton_dir.join("content");
 
-        let mut tasks: HashMap<String, Task> = HashMap::new();
-        for node in document.nodes() {
-            if node.name().value() != "task" {
-                continue;
+        let mut parent = Self::default();
+        if let Some(node) = document.get("extends") {
+            let extends = kdl_helpers::string_arg(&document, &document, node)?;
+            let parent_dir = normalize_path(skeleton_dir.as_path(), Path::new(&extends))?;
+            if !parent_dir.is_dir() {
+                return Err(ConfigError::from_missing_source(&document, node).into());
             }
 
-            let name = match node.get(0) {
-                Some(name) => match name.value().as_string() {
-                    Some(value) => Ok(value.to_owned()),
-                    None => Err(ConfigError::from_invalid_string_argument(
-                        &document, node, 0,
-                    )),
-                },
-                None => Err(ConfigError::from_missing_argument(&document, "task")),
-            }?;
+            chain.push(skeleton_dir.clone());
+            if chain.contains(&parent_dir) {
+                let mut names: Vec<String> = chain.iter().map(|dir| dir.display().to_string()).collect();
+                names.push(parent_dir.display().to_string());
+                return Err(ConfigError::from_extends_loop(&document, node, names).into());
+            }
 
-            if let Some(children) = node.children() {
-                let task = Task::from_kdl_document(children, name.to_owned())?;
-                tasks.insert(name, task);
+            parent = Self::read_extending(&parent_dir.join("skeleton.kdl"), chain)?;
+            chain.pop();
+        }
+
+        // parent content keeps pointing at the parent's files
+        for value in parent.content.values_mut() {
+            value.source = parent.root.join(&value.source);
+        }
+        for feature in parent.features.values_mut() {
+            for value in feature.content.values_mut() {
+                value.source = parent.root.join(&value.source);
             }
         }
 
-        let mut content: HashMap<String, Content> = HashMap::new();
+        let mut tasks = parent.tasks;
+        tasks.extend(task::tasks_from_kdl_document(&document, &document)?);
+
+        let mut content: HashMap<String, Content> = parent.content;
         let content_tree = fs_helpers::read_tree(&root.clone(), &root)?;
         for source in content_tree {
             content.insert(
@@ -59,140 +90,445 @@ impl SkeletonConfig {
             );
         }
 
+        let mut dependency_entries: Vec<(String, String, &KdlEntry)> = Vec::new();
+        configure_content_nodes(&document, &document, &mut content, &mut dependency_entries)?;
+
+        // content claimed by a feature is only included when it's enabled
+        let mut features: HashMap<String, Feature> = parent.features;
         for node in document.nodes() {
-            if node.name().value() != "content" {
+            if node.name().value() != "feature" {
                 continue;
             }
 
-            let source = match node.get(0) {
-                Some(entry) => match entry.value().as_string() {
-                    Some(value) => Ok(value.to_owned()),
-                    None => Err(ConfigError::from_invalid_string_argument(
-                        &document, node, 0,
-                    )),
-                },
-                None => Err(ConfigError::from_missing_argument(&document, "content")),
-            }?;
-
-            let content_val = match content.get_mut(&source) {
-                Some(value) => Ok(value),
-                None => Err(ConfigError::from_missing_source(&document, node)),
-            }?;
+            let name = kdl_helpers::string_arg(&document, &document, node)?;
+            let mut feature = features.remove(&name).unwrap_or_else(|| Feature {
+                name: name.clone(),
+                ..Default::default()
+            });
 
             if let Some(children) = node.children() {
-                for child in children.nodes().iter() {
-                    match child.name().value() {
-                        "destination" => {
-                            let destination =
-                                kdl_helpers::first_string_arg(children, "destination", || {
-                                    Ok("default".to_owned())
-                                })
-                                .unwrap();
-                            content_val.destination = PathBuf::from(destination);
-                        },
-                        "depends_on" => {
-                            for entry in child.entries() {
-                                if entry.name().is_some() {
-                                    continue;
-                                }
-
-                                content_val.dependencies.push(entry.value().as_string().unwrap().to_owned());
-                            }
-                        },
-                        _ => {}
-                    };
+                let claimed = configure_content_nodes(
+                    &document,
+                    children,
+                    &mut content,
+                    &mut dependency_entries,
+                )?;
+                for key in claimed {
+                    if let Some(value) = content.remove(&key) {
+                        feature.content.insert(key, value);
+                    }
                 }
+
+                feature.variables.extend(kdl_helpers::variables_from_kdl_document(children)?);
+                feature.tasks.extend(task::tasks_from_kdl_document(&document, children)?);
             }
+
+            features.insert(name, feature);
+        }
+
+        let mut all_content = content.clone();
+        for feature in features.values() {
+            all_content.extend(feature.content.clone());
         }
+        check_dependencies(&document, &all_content, &dependency_entries)?;
 
-        let variables = kdl_helpers::variables_from_kdl_document(&document)?;
+        let mut variables = parent.variables;
+        variables.extend(kdl_helpers::variables_from_kdl_document(&document)?);
 
         Ok(Self {
             root,
             content,
             tasks,
             variables,
+            features,
             is_default,
         })
     }
 
     pub fn calculate(&self) -> Vec<Content> {
-        let mut result: Vec<Content> = Vec::new();
+        calculate(&self.content)
+    }
+}
 
-        let mut collator = Collator::default();
-        let mut path_keys: Vec<PathBuf> = self.content.keys().map(PathBuf::from).collect();
+/// Applies the content nodes found in `nodes` to the content they name and
+/// returns every key that was configured. Glob rules are applied first so
+/// that nodes naming an exact path can override what a glob set.
+fn configure_content_nodes<'a>(
+    document: &KdlDocument,
+    nodes: &'a KdlDocument,
+    content: &mut HashMap<String, Content>,
+    dependency_entries: &mut Vec<(String, String, &'a KdlEntry)>,
+) -> Result<Vec<String>, ConfigError> {
+    let mut configured: Vec<String> = Vec::new();
+    let mut exact_nodes: Vec<(String, &KdlNode)> = Vec::new();
+    for node in nodes.nodes() {
+        if node.name().value() != "content" {
+            continue;
+        }
 
-        path_keys.sort_by(|a, b| {
-            let parent_a = a.parent().unwrap().to_str().unwrap();
-            let file_name_a = a.file_name().unwrap().to_str().unwrap();
+        let source = match node.get(0) {
+            Some(entry) => match entry.value().as_string() {
+                Some(value) => Ok(value.to_owned()),
+                None => Err(ConfigError::from_invalid_string_argument(
+                    document, node, 0,
+                )),
+            },
+            None => Err(ConfigError::from_missing_argument(nodes, "content")),
+        }?;
+
+        // paths that exist are never globs, even if they look like one,
+        // e.g. templated names like "{{ name }}.rs"
+        if content.contains_key(&source) || !is_glob(&source) {
+            exact_nodes.push((source, node));
+            continue;
+        }
 
-            let parent_b = b.parent().unwrap().to_str().unwrap();
-            let file_name_b = b.file_name().unwrap().to_str().unwrap();
+        let matcher = match GlobBuilder::new(&source).literal_separator(true).build() {
+            Ok(glob) => Ok(glob.compile_matcher()),
+            Err(err) => Err(ConfigError::from_invalid_glob(document, node, &err)),
+        }?;
+        let base = glob_base(&source);
 
-            let parent_cmp = collator.collate(parent_a, parent_b);
-            match parent_cmp {
-                Ordering::Equal => collator.collate(file_name_a, file_name_b),
-                _ => parent_cmp,
+        let mut matches: Vec<String> = content
+            .keys()
+            .filter(|key| matcher.is_match(key))
+            .cloned()
+            .collect();
+        matches.sort();
+
+        for key in matches {
+            if let Some(children) = node.children() {
+                let content_val = content.get_mut(&key).unwrap();
+                configure_content(
+                    document,
+                    children,
+                    &key,
+                    Some(&base),
+                    content_val,
+                    dependency_entries,
+                )?;
             }
+            configured.push(key);
+        }
+    }
+
+    for (source, node) in exact_nodes {
+        let content_val = match content.get_mut(&source) {
+            Some(value) => Ok(value),
+            None => Err(ConfigError::from_missing_source(document, node)),
+        }?;
+
+        if let Some(children) = node.children() {
+            configure_content(
+                document,
+                children,
+                &source,
+                None,
+                content_val,
+                dependency_entries,
+            )?;
+        }
+        configured.push(source);
+    }
+
+    Ok(configured)
+}
+
+/// Applies the children of a content node to one piece of content. For glob
+/// rules `base` is the literal directory the glob starts from, and a
+/// destination is treated as the directory matches are placed under.
+fn configure_content<'a>(
+    document: &KdlDocument,
+    children: &'a KdlDocument,
+    source: &str,
+    base: Option<&Path>,
+    content_val: &mut Content,
+    dependency_entries: &mut Vec<(String, String, &'a KdlEntry)>,
+) -> Result<(), ConfigError> {
+    let mut mode_node = None;
+    let mut target_node = None;
+    for child in children.nodes().iter() {
+        match child.name().value() {
+            "destination" => {
+                let destination = PathBuf::from(kdl_helpers::string_arg(document, children, child)?);
+                content_val.destination = match base {
+                    Some(base) => {
+                        let relative = Content::from_source(Path::new(source), None).destination;
+                        match relative.strip_prefix(base) {
+                            Ok(stripped) => destination.join(stripped),
+                            Err(_) => destination.join(relative),
+                        }
+                    },
+                    None => destination,
+                };
+            },
+            "kind" => {
+                let kind = kdl_helpers::string_arg(document, children, child)?;
+                content_val.kind = match ContentKind::from_str_opt(&kind) {
+                    Some(kind) => Ok(kind),
+                    None => Err(ConfigError::from_invalid_content_kind(
+                        document, child, &kind,
+                    )),
+                }?;
+            },
+            "policy" => {
+                let policy = kdl_helpers::string_arg(document, children, child)?;
+                content_val.policy = match Policy::from_str_opt(&policy) {
+                    Some(policy) => Ok(policy),
+                    None => Err(ConfigError::from_invalid_policy(
+                        document, child, &policy,
+                    )),
+                }?;
+            },
+            "mode" => {
+                let mode = kdl_helpers::string_arg(document, children, child)?;
+                content_val.mode = match parse_mode(&mode) {
+                    Some(mode) => Ok(Some(mode)),
+                    None => Err(ConfigError::from_invalid_mode(
+                        document, child, &mode,
+                    )),
+                }?;
+                mode_node = Some(child);
+            },
+            "for_each" => {
+                let variable = kdl_helpers::string_arg(document, children, child)?;
+                let item = match child.get("as") {
+                    Some(entry) => match entry.value().as_string() {
+                        Some(value) => Ok(value.to_owned()),
+                        None => Err(ConfigError::from_invalid_string_argument(
+                            document, child, "as",
+                        )),
+                    },
+                    None => Ok("item".to_owned()),
+                }?;
+
+                content_val.for_each = Some(ForEach { variable, item });
+            },
+            "when" => {
+                content_val.when = Some(kdl_helpers::string_arg(document, children, child)?);
+            },
+            "target" => {
+                content_val.target = Some(kdl_helpers::string_arg(document, children, child)?);
+                target_node = Some(child);
+            },
+            "depends_on" => {
+                let mut index = 0;
+                for entry in child.entries() {
+                    if entry.name().is_some() {
+                        continue;
+                    }
+
+                    let dependency = match entry.value().as_string() {
+                        Some(value) => Ok(value.to_owned()),
+                        None => Err(ConfigError::from_invalid_string_argument(
+                            document, child, index,
+                        )),
+                    }?;
+                    index += 1;
+
+                    content_val.dependencies.push(dependency.clone());
+                    dependency_entries.push((source.to_owned(), dependency, entry));
+                }
+            },
+            _ => {}
+        };
+    }
+
+    // checked once every child is read, as the kind may come after
+    if let Some(node) = mode_node {
+        if content_val.kind == ContentKind::Symlink {
+            return Err(ConfigError::from_unsupported_option(
+                document, node, &content_val.kind, "symlinks have no permissions of their own",
+            ));
+        }
+    }
+
+    if let Some(node) = target_node {
+        if content_val.kind != ContentKind::Symlink {
+            return Err(ConfigError::from_unsupported_option(
+                document, node, &content_val.kind, "only symlink content has a target",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_glob(source: &str) -> bool {
+    source.contains(['*', '?', '[', '{'])
+}
+
+/// The leading directories of a glob that contain no special characters.
+fn glob_base(glob: &str) -> PathBuf {
+    let mut components: Vec<&str> = glob.split('/').take_while(|c| !is_glob(c)).collect();
+    // the last literal component is a file name, not a directory
+    if components.len() == glob.split('/').count() {
+        components.pop();
+    }
+
+    PathBuf::from(components.join("/"))
+}
+
+/// Makes sure every dependency names known content and that no content
+/// depends on itself, directly or through other content.
+fn check_dependencies(
+    document: &KdlDocument,
+    content: &HashMap<String, Content>,
+    dependency_entries: &[(String, String, &KdlEntry)],
+) -> Result<(), ConfigError> {
+    for (_, dependency, entry) in dependency_entries {
+        if !content.contains_key(dependency) {
+            return Err(ConfigError::from_unknown_dependency(document, entry, dependency));
+        }
+    }
+
+    if let Some(path) = find_dependency_loop(content) {
+        // point at the last depends_on entry of this document in the loop,
+        // the others may have been declared by the skeleton it extends
+        let entry = path.windows(2).rev().find_map(|edge| {
+            dependency_entries
+                .iter()
+                .find(|(key, dependency, _)| *key == edge[0] && *dependency == edge[1])
+                .map(|(_, _, entry)| *entry)
         });
 
-        let keys: Vec<String> = path_keys.clone()
-            .iter()
-            .map(|k| k.to_str().unwrap().to_owned())
-            .collect();
+        return Err(match entry {
+            Some(entry) => ConfigError::from_dependency_loop(document, entry, path),
+            None => ConfigError::from_inherited_dependency_loop(document, document.get("extends"), path),
+        });
+    }
 
-        let mut dependents: HashMap<String, Vec<String>> = keys.clone()
-            .iter()
-            .map(|k| (k.to_owned(), vec![]))
-            .collect();
+    Ok(())
+}
+
+/// Returns the first dependency loop found, as the list of content paths
+/// walked to get back to where it started.
+fn find_dependency_loop(content: &HashMap<String, Content>) -> Option<Vec<String>> {
+    let mut keys: Vec<&String> = content.keys().collect();
+    keys.sort();
+
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut stack: Vec<&str> = Vec::new();
+    for key in keys {
+        if let Some(path) = visit_dependencies(content, key, &mut visited, &mut stack) {
+            return Some(path);
+        }
+    }
+
+    None
+}
 
-        let mut dependencies = dependents.clone();
+fn visit_dependencies<'a>(
+    content: &'a HashMap<String, Content>,
+    key: &'a str,
+    visited: &mut HashSet<&'a str>,
+    stack: &mut Vec<&'a str>,
+) -> Option<Vec<String>> {
+    if let Some(position) = stack.iter().position(|k| *k == key) {
+        let mut path: Vec<String> = stack[position..].iter().map(|k| k.to_string()).collect();
+        path.push(key.to_owned());
+        return Some(path);
+    }
 
-        for key in keys.clone() {
-            let content = self.content.get(&key).unwrap();
+    if !visited.insert(key) {
+        return None;
+    }
 
-            dependencies.get_mut(&key).unwrap().extend(content.dependencies.clone());
-            for dep in &content.dependencies {
-                dependents.get_mut(dep).unwrap().push(key.clone());
+    stack.push(key);
+    if let Some(entry) = content.get(key) {
+        for dependency in &entry.dependencies {
+            if let Some(path) = visit_dependencies(content, dependency, visited, stack) {
+                return Some(path);
             }
         }
+    }
+    stack.pop();
 
-        let mut remaining = keys.clone();
-        while !remaining.is_empty() {
-            let mut count = 0;
-            for key in remaining.clone() {
-                if !dependencies.contains_key(&key) {
-                    continue;
-                }
+    None
+}
 
-                let content = self.content.get(&key).unwrap();
-                let deps = dependencies.get_mut(&key).unwrap();
+/// Orders content so that every entry comes after the entries it depends on.
+/// Entries without dependencies between them are sorted by path.
+pub fn calculate(content: &HashMap<String, Content>) -> Vec<Content> {
+    let mut result: Vec<Content> = Vec::new();
 
-                if deps.is_empty() {
-                    count += 1;
-                    // push to result
-                    result.push(content.clone());
+    let mut collator = Collator::default();
+    let mut path_keys: Vec<PathBuf> = content.keys().map(PathBuf::from).collect();
 
-                    // remove from remaining
-                    remaining.retain(|name| *name != key);
+    path_keys.sort_by(|a, b| {
+        let parent_a = a.parent().unwrap().to_str().unwrap();
+        let file_name_a = a.file_name().unwrap().to_str().unwrap();
 
-                    // loop dependents to remove ourselves from their dependencies
-                    for dependent in dependents.get(&key).unwrap() {
-                        dependencies.get_mut(dependent).unwrap().retain(|name| *name != *key);
-                    }
-                }
+        let parent_b = b.parent().unwrap().to_str().unwrap();
+        let file_name_b = b.file_name().unwrap().to_str().unwrap();
+
+        let parent_cmp = collator.collate(parent_a, parent_b);
+        match parent_cmp {
+            Ordering::Equal => collator.collate(file_name_a, file_name_b),
+            _ => parent_cmp,
+        }
+    });
+
+    let keys: Vec<String> = path_keys.clone()
+        .iter()
+        .map(|k| k.to_str().unwrap().to_owned())
+        .collect();
+
+    let mut dependents: HashMap<String, Vec<String>> = keys.clone()
+        .iter()
+        .map(|k| (k.to_owned(), vec![]))
+        .collect();
+
+    let mut dependencies = dependents.clone();
+
+    for key in keys.clone() {
+        let entry = content.get(&key).unwrap();
+
+        // unknown dependencies are rejected when the config is read
+        for dep in entry.dependencies.iter().filter(|dep| content.contains_key(*dep)) {
+            dependencies.get_mut(&key).unwrap().push(dep.clone());
+            dependents.get_mut(dep).unwrap().push(key.clone());
+        }
+    }
+
+    let mut remaining = keys.clone();
+    while !remaining.is_empty() {
+        let mut count = 0;
+        for key in remaining.clone() {
+            if !dependencies.contains_key(&key) {
+                continue;
             }
 
-            if count == 0 {
-                panic!("dependency loop detected");
+            let entry = content.get(&key).unwrap();
+            let deps = dependencies.get_mut(&key).unwrap();
+
+            if deps.is_empty() {
+                count += 1;
+                // push to result
+                result.push(entry.clone());
+
+                // remove from remaining
+                remaining.retain(|name| *name != key);
+
+                // loop dependents to remove ourselves from their dependencies
+                for dependent in dependents.get(&key).unwrap() {
+                    dependencies.get_mut(dependent).unwrap().retain(|name| *name != *key);
+                }
             }
         }
 
-        result
+        // loops are rejected when the config is read, so this only happens
+        // for content that was assembled by hand
+        if count == 0 {
+            panic!("dependency loop detected");
+        }
     }
+
+    result
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::match_like_matches_macro)]
 mod tests {
     use super::*;
 
@@ -420,6 +756,302 @@ mod tests {
             assert_eq!(skeleton.variables, Context::new());
         }
 
+        #[test]
+        fn allows_setting_kind() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "{{ name }}").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "one" {
+                kind "template"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            let content = skeleton.content.get("one").unwrap();
+            assert_eq!(content.kind, ContentKind::Template);
+        }
+
+        #[test]
+        fn errors_when_kind_is_invalid() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "").unwrap();
+
+            let config = r#"
+            content "one" {
+                kind "tempalte"
+            }
+            "#;
+            fs::write(dir.path().join("skeleton.kdl"), config).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::InvalidContentKind("tempalte".to_owned()));
+            assert_eq!(err.help, Some("did you mean \"template\"?".to_owned()));
+            assert_eq!(err.span.offset(), config.find("\"tempalte\"").unwrap());
+        }
+
+        #[test]
+        fn allows_setting_symlink_targets() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/clang-format"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "clang-format" {
+                kind "symlink"
+                target "../../.clang-format"
+                destination ".clang-format"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            let content = skeleton.content.get("clang-format").unwrap();
+            assert_eq!(content.kind, ContentKind::Symlink);
+            assert_eq!(content.target, Some("../../.clang-format".to_owned()));
+        }
+
+        #[test]
+        fn allows_setting_policy() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/README.md"), "").unwrap();
+            fs::write(dir.path().join("content/Cargo.toml"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "README.md" {
+                policy "create_only"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            assert_eq!(skeleton.content.get("README.md").unwrap().policy, Policy::CreateOnly);
+            assert_eq!(skeleton.content.get("Cargo.toml").unwrap().policy, Policy::Overwrite);
+        }
+
+        #[test]
+        fn errors_when_policy_is_invalid() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "").unwrap();
+
+            let config = r#"
+            content "one" {
+                policy "create-only"
+            }
+            "#;
+            fs::write(dir.path().join("skeleton.kdl"), config).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::InvalidPolicy("create-only".to_owned()));
+            assert_eq!(err.help, Some("did you mean \"create_only\"?".to_owned()));
+        }
+
+        #[test]
+        fn allows_setting_mode() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/build.sh"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "build.sh" {
+                mode "0755"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            assert_eq!(skeleton.content.get("build.sh").unwrap().mode, Some(0o755));
+        }
+
+        #[test]
+        fn errors_when_mode_is_invalid() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/build.sh"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "build.sh" {
+                mode "rwxr-xr-x"
+            }
+            "#).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::InvalidMode("rwxr-xr-x".to_owned()));
+        }
+
+        #[test]
+        fn errors_when_target_is_set_on_files() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/config"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "config" {
+                target "../config"
+            }
+            "#).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::UnsupportedOption("target".to_owned(), ContentKind::File));
+        }
+
+        #[test]
+        fn errors_when_mode_is_set_on_symlinks() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/config"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "config" {
+                mode "0755"
+                kind "symlink"
+            }
+            "#).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::UnsupportedOption("mode".to_owned(), ContentKind::Symlink));
+        }
+
+        #[test]
+        fn applies_glob_rules_to_matching_content() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir_all(dir.path().join("content/src/nested")).unwrap();
+            fs::write(dir.path().join("content/src/lib.rs"), "").unwrap();
+            fs::write(dir.path().join("content/src/nested/mod.rs"), "").unwrap();
+            fs::write(dir.path().join("content/src/nested/dot_keep"), "").unwrap();
+            fs::write(dir.path().join("content/main.rs"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "src/nested/mod.rs" {
+                kind "file"
+            }
+            content "src/**/*" {
+                kind "template"
+                destination "lib"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+
+            let lib = skeleton.content.get("src/lib.rs").unwrap();
+            assert_eq!(lib.kind, ContentKind::Template);
+            assert_eq!(lib.destination, PathBuf::from("lib/lib.rs"));
+
+            let keep = skeleton.content.get("src/nested/dot_keep").unwrap();
+            assert_eq!(keep.kind, ContentKind::Template);
+            assert_eq!(keep.destination, PathBuf::from("lib/nested/.keep"));
+
+            // exact paths override globs, no matter the order in the file
+            let module = skeleton.content.get("src/nested/mod.rs").unwrap();
+            assert_eq!(module.kind, ContentKind::File);
+            assert_eq!(module.destination, PathBuf::from("lib/nested/mod.rs"));
+
+            let main = skeleton.content.get("main.rs").unwrap();
+            assert_eq!(main.kind, ContentKind::File);
+            assert_eq!(main.destination, PathBuf::from("main.rs"));
+        }
+
+        #[test]
+        fn treats_existing_paths_as_exact() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir_all(dir.path().join("content/{{ name }}")).unwrap();
+            fs::write(dir.path().join("content/{{ name }}/lib.rs"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "{{ name }}/lib.rs" {
+                kind "template"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            let lib = skeleton.content.get("{{ name }}/lib.rs").unwrap();
+            assert_eq!(lib.kind, ContentKind::Template);
+        }
+
+        #[test]
+        fn errors_when_glob_is_invalid() {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"
+                content "src/[*.rs"
+            "#
+            )
+            .unwrap();
+
+            let result = SkeletonConfig::read_from(&file.path().to_path_buf());
+            let is_invalid_glob_error = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err.kind == ConfigErrorKind::InvalidGlob("src/[*.rs".to_owned()),
+                _ => false,
+            };
+            assert!(is_invalid_glob_error);
+        }
+
+        #[test]
+        fn allows_setting_for_each() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "").unwrap();
+            fs::write(dir.path().join("content/two"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "one" {
+                for_each "services" as="service"
+            }
+            content "two" {
+                for_each "services"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            assert_eq!(skeleton.content.get("one").unwrap().for_each, Some(ForEach {
+                variable: "services".to_owned(),
+                item: "service".to_owned(),
+            }));
+            assert_eq!(skeleton.content.get("two").unwrap().for_each, Some(ForEach {
+                variable: "services".to_owned(),
+                item: "item".to_owned(),
+            }));
+        }
+
+        #[test]
+        fn allows_setting_when() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/Dockerfile"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "Dockerfile" {
+                when "{{ use_docker }}"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl")).unwrap();
+            let content = skeleton.content.get("Dockerfile").unwrap();
+            assert_eq!(content.when, Some("{{ use_docker }}".to_owned()));
+        }
+
         #[test]
         fn allows_declaring_dependencies() {
             let dir = TempDir::new().unwrap();
@@ -467,6 +1099,197 @@ mod tests {
             assert_eq!(steps[2].source, PathBuf::from("one"));
         }
 
+        #[test]
+        fn errors_when_dependency_is_unknown() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "").unwrap();
+
+            let config = r#"
+            content "one" {
+                depends_on "missing"
+            }
+            "#;
+            fs::write(dir.path().join("skeleton.kdl"), config).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::UnknownDependency("missing".to_owned()));
+            assert_eq!(err.span.offset(), config.find("\"missing\"").unwrap());
+        }
+
+        #[test]
+        fn errors_when_dependency_is_not_a_string() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "").unwrap();
+
+            fs::write(dir.path().join("skeleton.kdl"), r#"
+            content "one" {
+                depends_on 1
+            }
+            "#).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let is_invalid_string_error = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err.kind == ConfigErrorKind::InvalidString,
+                _ => false,
+            };
+            assert!(is_invalid_string_error);
+        }
+
+        #[test]
+        fn errors_when_dependencies_loop() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("content")).unwrap();
+            fs::write(dir.path().join("content/one"), "").unwrap();
+            fs::write(dir.path().join("content/two"), "").unwrap();
+            fs::write(dir.path().join("content/three"), "").unwrap();
+
+            let config = r#"
+            content "one" {
+                depends_on "two"
+            }
+            content "two" {
+                depends_on "three"
+            }
+            content "three" {
+                depends_on "one"
+            }
+            "#;
+            fs::write(dir.path().join("skeleton.kdl"), config).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::DependencyLoop(vec![
+                "one".to_owned(),
+                "two".to_owned(),
+                "three".to_owned(),
+                "one".to_owned(),
+            ]));
+            assert_eq!(err.span.offset(), config.find("depends_on \"one\"").unwrap() + 11);
+            assert_eq!(err.to_string(), "dependency loop detected: one -> two -> three -> one");
+        }
+
+        #[test]
+        fn layers_on_top_of_extended_skeleton() {
+            let dir = TempDir::new().unwrap();
+            let base = dir.path().join("base");
+            let child = dir.path().join("child");
+            fs::create_dir_all(base.join("content")).unwrap();
+            fs::create_dir_all(child.join("content")).unwrap();
+
+            fs::write(base.join("content/one"), "base").unwrap();
+            fs::write(base.join("content/two"), "base").unwrap();
+            fs::write(base.join("skeleton.kdl"), r#"
+            variables {
+                name "base"
+                license "MIT"
+            }
+            task "test" {
+                exec "true"
+            }
+            "#).unwrap();
+
+            fs::write(child.join("content/two"), "child").unwrap();
+            fs::write(child.join("content/three"), "child").unwrap();
+            fs::write(child.join("skeleton.kdl"), r#"
+            extends "../base"
+            variables {
+                name "child"
+            }
+            content "one" {
+                kind "template"
+                depends_on "three"
+            }
+            "#).unwrap();
+
+            let skeleton = SkeletonConfig::read_from(&child.join("skeleton.kdl")).unwrap();
+            assert_eq!(skeleton.root, child.join("content"));
+
+            let one = skeleton.content.get("one").unwrap();
+            assert_eq!(one.source, base.join("content/one"));
+            assert_eq!(one.destination, PathBuf::from("one"));
+            assert_eq!(one.kind, ContentKind::Template);
+            assert_eq!(one.dependencies, vec!["three"]);
+
+            assert_eq!(skeleton.content.get("two").unwrap().source, PathBuf::from("two"));
+            assert_eq!(skeleton.content.get("three").unwrap().source, PathBuf::from("three"));
+
+            assert_eq!(skeleton.variables.get("name").unwrap(), "child");
+            assert_eq!(skeleton.variables.get("license").unwrap(), "MIT");
+            assert!(skeleton.tasks.contains_key("test"));
+        }
+
+        #[test]
+        fn errors_when_extended_skeleton_is_missing() {
+            let dir = TempDir::new().unwrap();
+            fs::write(dir.path().join("skeleton.kdl"), r#"extends "../missing""#).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("skeleton.kdl"));
+            let is_missing_source_error = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err.kind == ConfigErrorKind::MissingSource,
+                _ => false,
+            };
+            assert!(is_missing_source_error);
+        }
+
+        #[test]
+        fn errors_when_extends_loops() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("one")).unwrap();
+            fs::create_dir(dir.path().join("two")).unwrap();
+            fs::write(dir.path().join("one/skeleton.kdl"), r#"extends "../two""#).unwrap();
+            fs::write(dir.path().join("two/skeleton.kdl"), r#"extends "../one""#).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("one/skeleton.kdl"));
+            let is_extends_loop_error = match result.unwrap_err() {
+                SkelError::ConfigError(err) => matches!(err.kind, ConfigErrorKind::ExtendsLoop(chain) if chain.len() == 3),
+                _ => false,
+            };
+            assert!(is_extends_loop_error);
+        }
+
+        #[test]
+        fn errors_when_dependencies_loop_through_the_parent() {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir_all(dir.path().join("parent/content")).unwrap();
+            fs::create_dir_all(dir.path().join("child/content")).unwrap();
+            fs::write(dir.path().join("parent/content/a"), "").unwrap();
+            fs::write(dir.path().join("parent/content/b"), "").unwrap();
+            fs::write(dir.path().join("parent/skeleton.kdl"), r#"
+            content "b" {
+                depends_on "a"
+            }
+            "#).unwrap();
+
+            let config = r#"
+            extends "../parent"
+            content "a" {
+                depends_on "b"
+            }
+            "#;
+            fs::write(dir.path().join("child/skeleton.kdl"), config).unwrap();
+
+            let result = SkeletonConfig::read_from(&dir.path().join("child/skeleton.kdl"));
+            let err = match result.unwrap_err() {
+                SkelError::ConfigError(err) => err,
+                err => panic!("unexpected error: {:?}", err),
+            };
+            assert_eq!(err.kind, ConfigErrorKind::DependencyLoop(vec![
+                "a".to_owned(),
+                "b".to_owned(),
+                "a".to_owned(),
+            ]));
+            assert_eq!(err.span.offset(), config.find("\"b\"").unwrap());
+        }
+
         #[test]
         fn ignores_non_destination_children() {
             let dir = TempDir::new().unwrap();
@@ -18,6 +18,28 @@ pub enum TaskStep {
     Task(String, Vec<String>),
 }
 
+/// Reads every task node in `nodes`. Spans in errors point into `document`,
+/// the config the nodes came from.
+pub fn tasks_from_kdl_document(
+    document: &KdlDocument,
+    nodes: &KdlDocument,
+) -> Result<HashMap<String, Task>, SkelError> {
+    let mut tasks: HashMap<String, Task> = HashMap::new();
+    for node in nodes.nodes() {
+        if node.name().value() != "task" {
+            continue;
+        }
+
+        let name = kdl_helpers::string_arg(document, nodes, node)?;
+        if let Some(children) = node.children() {
+            let task = Task::from_kdl_document(children, name.to_owned())?;
+            tasks.insert(name, task);
+        }
+    }
+
+    Ok(tasks)
+}
+
 impl Task {
     pub fn from_kdl_document(doc: &KdlDocument, name: String) -> Result<Self, SkelError> {
         let mut steps: Vec<TaskStep> = Vec::new();
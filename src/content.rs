@@ -6,35 +6,123 @@ pub struct Content {
     pub destination: PathBuf,
     pub kind: ContentKind,
     pub dependencies: Vec<String>,
+    pub for_each: Option<ForEach>,
+    pub when: Option<String>,
+    pub policy: Policy,
+    /// Where a symlink points, instead of the contents of its source
+    pub target: Option<String>,
+    /// Permissions to write with, instead of those of the source
+    pub mode: Option<u32>,
+}
+
+/// Renders content once per item of a list variable, with the item
+/// available to the template and destination under its own name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ForEach {
+    pub variable: String,
+    pub item: String,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ContentKind {
     File,
     Template,
+    Symlink,
 }
 
 const STR_FILE: &str = "file";
 const STR_TEMPLATE: &str = "template";
+const STR_SYMLINK: &str = "symlink";
 
 impl ContentKind {
-    fn from_str_opt(input: Option<&str>) -> ContentKind {
-        match input {
-            Some(input) => match input.trim().to_lowercase().as_ref() {
-                STR_FILE => ContentKind::File,
-                STR_TEMPLATE => ContentKind::Template,
-                _ => panic!("invalid content kind: {}", &input),
-            },
-            None => ContentKind::File,
+    pub const NAMES: [&'static str; 3] = [STR_FILE, STR_TEMPLATE, STR_SYMLINK];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentKind::File => STR_FILE,
+            ContentKind::Template => STR_TEMPLATE,
+            ContentKind::Symlink => STR_SYMLINK,
+        }
+    }
+
+    pub fn from_str_opt(input: &str) -> Option<ContentKind> {
+        match input.trim().to_lowercase().as_ref() {
+            STR_FILE => Some(ContentKind::File),
+            STR_TEMPLATE => Some(ContentKind::Template),
+            STR_SYMLINK => Some(ContentKind::Symlink),
+            _ => None,
         }
     }
 }
 
+/// How apply treats a destination that already exists. Create-only content
+/// seeds a file the project owns from then on, and ignored content is never
+/// written at all.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Policy {
+    #[default]
+    Overwrite,
+    CreateOnly,
+    Ignore,
+}
+
+const STR_OVERWRITE: &str = "overwrite";
+const STR_CREATE_ONLY: &str = "create_only";
+const STR_IGNORE: &str = "ignore";
+
+impl Policy {
+    pub const NAMES: [&'static str; 3] = [STR_OVERWRITE, STR_CREATE_ONLY, STR_IGNORE];
+
+    pub fn from_str_opt(input: &str) -> Option<Policy> {
+        match input.trim().to_lowercase().as_ref() {
+            STR_OVERWRITE => Some(Policy::Overwrite),
+            STR_CREATE_ONLY => Some(Policy::CreateOnly),
+            STR_IGNORE => Some(Policy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// Parses permissions written in octal, like "0755".
+pub fn parse_mode(input: &str) -> Option<u32> {
+    let input = input.trim();
+    let digits = input.strip_prefix("0o").unwrap_or(input);
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+
+    u32::from_str_radix(digits, 8).ok()
+}
+
 impl Content {
-    pub fn from_source(path: &Path, kind: Option<&str>) -> Self {
+    /// Builds content from a file in the content tree. The `symlink_` and
+    /// `executable_` prefixes can come in either order, followed by `dot_`.
+    /// Symlinks have no permissions of their own, so `executable_` does
+    /// nothing for them.
+    pub fn from_source(path: &Path, kind: Option<ContentKind>) -> Self {
         let source = path.to_path_buf();
         let mut destination = PathBuf::from(source.parent().unwrap());
-        let file_name: String = source.file_name().unwrap().to_string_lossy().into();
+        let mut file_name: String = source.file_name().unwrap().to_string_lossy().into();
+
+        let mut prefix_kind = None;
+        let mut mode = None;
+        loop {
+            if let Some(stripped) = file_name.strip_prefix("symlink_") {
+                prefix_kind = Some(ContentKind::Symlink);
+                file_name = stripped.to_owned();
+            } else if let Some(stripped) = file_name.strip_prefix("executable_") {
+                mode = Some(0o755);
+                file_name = stripped.to_owned();
+            } else {
+                break;
+            }
+        }
+
+        let kind = kind.or(prefix_kind).unwrap_or(ContentKind::File);
+        if kind == ContentKind::Symlink {
+            mode = None;
+        }
+
         if file_name.starts_with("dot_") {
             destination.push(format!(".{}", file_name.strip_prefix("dot_").unwrap()));
         } else {
@@ -44,15 +132,20 @@ impl Content {
         Self {
             source,
             destination,
-            kind: ContentKind::from_str_opt(kind),
+            kind,
             dependencies: Vec::new(),
+            for_each: None,
+            when: None,
+            policy: Policy::Overwrite,
+            target: None,
+            mode,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Content, ContentKind};
+    use super::{parse_mode, Content, ContentKind};
     use std::fs;
     use tempfile::TempDir;
 
@@ -82,6 +175,44 @@ mod tests {
         assert_eq!(content.kind, ContentKind::File);
     }
 
+    #[test]
+    fn from_source_symlink_prefix() {
+        let root = TempDir::new().unwrap();
+        let full_path = root.path().join("symlink_dot_clang-format");
+
+        fs::write(&full_path, "../../.clang-format").unwrap();
+
+        let content = Content::from_source(&full_path, None);
+        assert_eq!(content.source, full_path);
+        assert_eq!(content.destination, root.path().join(".clang-format"));
+        assert_eq!(content.kind, ContentKind::Symlink);
+    }
+
+    #[test]
+    fn from_source_executable_prefix() {
+        let root = TempDir::new().unwrap();
+        let full_path = root.path().join("executable_dot_envrc");
+
+        fs::write(&full_path, "").unwrap();
+
+        let content = Content::from_source(&full_path, None);
+        assert_eq!(content.destination, root.path().join(".envrc"));
+        assert_eq!(content.kind, ContentKind::File);
+        assert_eq!(content.mode, Some(0o755));
+    }
+
+    #[test]
+    fn from_source_prefixes_in_any_order() {
+        let root = TempDir::new().unwrap();
+
+        for name in ["executable_symlink_dot_x", "symlink_executable_dot_x"] {
+            let content = Content::from_source(&root.path().join(name), None);
+            assert_eq!(content.destination, root.path().join(".x"));
+            assert_eq!(content.kind, ContentKind::Symlink);
+            assert_eq!(content.mode, None);
+        }
+    }
+
     #[test]
     fn from_source_kind_file() {
         let root = TempDir::new().unwrap();
@@ -89,7 +220,7 @@ mod tests {
 
         fs::write(&full_path, "").unwrap();
 
-        let content = Content::from_source(&full_path, Some("file"));
+        let content = Content::from_source(&full_path, Some(ContentKind::File));
         assert_eq!(content.source, full_path);
         assert_eq!(content.destination, full_path);
         assert_eq!(content.kind, ContentKind::File);
@@ -101,9 +232,27 @@ mod tests {
         let full_path = root.path().join("file.template");
         fs::write(&full_path, "").unwrap();
 
-        let content = Content::from_source(&full_path, Some("template"));
+        let content = Content::from_source(&full_path, Some(ContentKind::Template));
         assert_eq!(content.source, full_path);
         assert_eq!(content.destination, root.path().join("file.template"));
         assert_eq!(content.kind, ContentKind::Template);
     }
+
+    #[test]
+    fn kind_from_str_opt() {
+        assert_eq!(ContentKind::from_str_opt("file"), Some(ContentKind::File));
+        assert_eq!(ContentKind::from_str_opt(" Template "), Some(ContentKind::Template));
+        assert_eq!(ContentKind::from_str_opt("symlink"), Some(ContentKind::Symlink));
+        assert_eq!(ContentKind::from_str_opt("directory"), None);
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(parse_mode("0755"), Some(0o755));
+        assert_eq!(parse_mode("644"), Some(0o644));
+        assert_eq!(parse_mode("0o600"), Some(0o600));
+        assert_eq!(parse_mode("0799"), None);
+        assert_eq!(parse_mode("rwxr-xr-x"), None);
+        assert_eq!(parse_mode(""), None);
+    }
 }
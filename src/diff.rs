@@ -0,0 +1,149 @@
+use std::path::Path;
+
+use owo_colors::OwoColorize;
+use similar::TextDiff;
+
+use crate::error::SkelError;
+use crate::plan::{Action, Existing, Plan};
+use crate::skeleton::Skeleton;
+use crate::util::file_mode;
+
+/// Returns a unified diff for every destination whose contents differ from
+/// what the skeleton would produce, in dependency order, followed by one
+/// for its permissions if those differ too.
+pub fn diff(skeleton: &Skeleton) -> Result<Vec<String>, SkelError> {
+    let plan = Plan::from_skeleton(skeleton)?;
+    let mut diffs: Vec<String> = Vec::new();
+
+    for step in plan.steps {
+        if step.action == Action::Skip {
+            continue;
+        }
+
+        let destination = skeleton.project.join(&step.destination);
+
+        if step.action != Action::Chmod {
+            // symlinks are compared by their targets
+            let existing = Existing::read(&destination)?;
+
+            let new = match step.action {
+                Action::Delete | Action::Orphan | Action::Modified => None,
+                _ => Some(step.data.as_slice()),
+            };
+            diffs.push(unified_diff(&step.destination, existing.as_ref().map(Existing::data), new));
+        }
+
+        if matches!(step.action, Action::Overwrite | Action::Chmod) {
+            if let (Some(old), Some(new)) = (file_mode(&destination)?, step.mode) {
+                if old != new {
+                    diffs.push(mode_diff(&step.destination, old, new));
+                }
+            }
+        }
+    }
+
+    Ok(diffs)
+}
+
+/// Shows a change of permissions the way a diff shows a changed line.
+pub fn mode_diff(destination: &Path, old: u32, new: u32) -> String {
+    format!(
+        "--- a/{0}\n+++ b/{0}\n-mode {1:04o}\n+mode {2:04o}\n",
+        destination.display(),
+        old,
+        new,
+    )
+}
+
+/// Diffs the current contents of a file, or `None` if it doesn't exist,
+/// against the contents the skeleton would write there, or `None` if it
+/// would delete it.
+pub fn unified_diff(destination: &Path, old: Option<&[u8]>, new: Option<&[u8]>) -> String {
+    let old_header = match old {
+        Some(_) => format!("a/{}", destination.display()),
+        None => "/dev/null".to_owned(),
+    };
+    let new_header = match new {
+        Some(_) => format!("b/{}", destination.display()),
+        None => "/dev/null".to_owned(),
+    };
+
+    let old_text = std::str::from_utf8(old.unwrap_or_default());
+    let new_text = std::str::from_utf8(new.unwrap_or_default());
+    match (old_text, new_text) {
+        (Ok(old_text), Ok(new_text)) => TextDiff::from_lines(old_text, new_text)
+            .unified_diff()
+            .header(&old_header, &new_header)
+            .to_string(),
+        _ => format!("Binary files {} and {} differ\n", old_header, new_header),
+    }
+}
+
+pub fn colorize(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let line = if line.starts_with("---") || line.starts_with("+++") {
+                line.bold().to_string()
+            } else if line.starts_with('+') {
+                line.green().to_string()
+            } else if line.starts_with('-') {
+                line.red().to_string()
+            } else if line.starts_with("@@") {
+                line.cyan().to_string()
+            } else {
+                line.to_owned()
+            };
+
+            format!("{}\n", line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::test_helpers::project_with_content;
+
+    #[test]
+    fn diffs_modified_and_missing_files() {
+        let project = project_with_content(&[("one.txt", "one\n"), ("two.txt", "two\nthree\n"), ("three.txt", "three\n")]);
+
+        fs::write(project.path().join("two.txt"), "two\nfour\n").unwrap();
+        fs::write(project.path().join("three.txt"), "three\n").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        assert_eq!(diff(&skeleton).unwrap(), vec![
+            "--- /dev/null\n+++ b/one.txt\n@@ -0,0 +1 @@\n+one\n".to_owned(),
+            "--- a/two.txt\n+++ b/two.txt\n@@ -1,2 +1,2 @@\n two\n-four\n+three\n".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn diffs_deleted_files() {
+        let result = unified_diff(&PathBuf::from("old.txt"), Some(b"old\n"), None);
+        assert_eq!(result, "--- a/old.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-old\n");
+    }
+
+    #[test]
+    fn diffs_permissions() {
+        let result = mode_diff(&PathBuf::from("build.sh"), 0o644, 0o755);
+        assert_eq!(result, "--- a/build.sh\n+++ b/build.sh\n-mode 0644\n+mode 0755\n");
+    }
+
+    #[test]
+    fn reports_binary_files() {
+        let result = unified_diff(&PathBuf::from("bin"), Some(&[0xff, 0x00]), Some(&[0xfe]));
+        assert_eq!(result, "Binary files a/bin and b/bin differ\n");
+    }
+
+    #[test]
+    fn colorizes_changed_lines() {
+        let result = colorize("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-old\n+new\n same\n");
+        assert!(result.contains(&format!("{}\n", "-old".red())));
+        assert!(result.contains(&format!("{}\n", "+new".green())));
+        assert!(result.ends_with(" same\n"));
+    }
+}
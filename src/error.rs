@@ -1,7 +1,12 @@
-use kdl::{KdlDocument, KdlEntry, KdlNode};
+use std::path::PathBuf;
+
+use kdl::{KdlDocument, KdlEntry, KdlNode, NodeKey};
 use miette::{Diagnostic, SourceSpan};
 use thiserror::Error;
 
+use crate::content::{ContentKind, Policy};
+use crate::util::did_you_mean;
+
 #[derive(Debug, Diagnostic, Error)]
 pub enum SkelError {
     #[error(transparent)]
@@ -16,6 +21,75 @@ pub enum SkelError {
     #[diagnostic(transparent)]
     ConfigError(#[from] ConfigError),
 
+    #[error(transparent)]
+    #[diagnostic(code(skel::template_error))]
+    TemplateError(#[from] tera::Error),
+
+    #[error("unknown task: {0}")]
+    #[diagnostic(code(skel::unknown_task))]
+    UnknownTask(String),
+
+    #[error("task {0} failed: {1}")]
+    #[diagnostic(code(skel::task_failed))]
+    TaskFailed(String, std::process::ExitStatus),
+
+    #[error("task loop detected: {}", .0.join(" -> "))]
+    #[diagnostic(code(skel::task_loop))]
+    TaskLoop(Vec<String>),
+
+    #[error("unknown feature: {0}")]
+    #[diagnostic(code(skel::unknown_feature))]
+    UnknownFeature(String),
+
+    #[error("{0} is provided by both {1} and feature {2}")]
+    #[diagnostic(
+        code(skel::feature_conflict),
+        help("features can only add content, not replace it")
+    )]
+    FeatureConflict(String, String, String),
+
+    #[error("{} is provided by both {} and {}", .0.display(), .1.display(), .2.display())]
+    #[diagnostic(code(skel::destination_conflict))]
+    DestinationConflict(PathBuf, PathBuf, PathBuf),
+
+    #[error("{0} is in both {} and {}", .1.display(), .2.display())]
+    #[diagnostic(
+        code(skel::duplicate_content),
+        help("mount one of the skeletons somewhere else")
+    )]
+    DuplicateContent(String, PathBuf, PathBuf),
+
+    #[error("destination {} is outside the project", .0.display())]
+    #[diagnostic(
+        code(skel::invalid_destination),
+        help("destinations have to be relative paths without \"..\"")
+    )]
+    InvalidDestination(PathBuf),
+
+    #[error("for_each variable {0} is not a list")]
+    #[diagnostic(code(skel::invalid_for_each))]
+    InvalidForEach(String),
+
+    #[error("{0}")]
+    #[diagnostic(code(skel::git_error))]
+    GitError(String),
+
+    #[error("could not extract {0}: {1}")]
+    #[diagnostic(code(skel::archive_error))]
+    ArchiveError(PathBuf, String),
+
+    #[error("{} does not exist", .0.display())]
+    #[diagnostic(code(skel::missing_lockfile), help("apply the skeleton before updating it"))]
+    MissingLockfile(PathBuf),
+
+    #[error("nothing to undo")]
+    #[diagnostic(code(skel::nothing_to_undo))]
+    NothingToUndo,
+
+    #[error("unknown history entry: {0}")]
+    #[diagnostic(code(skel::unknown_history_entry), help("entries are the directories in .skeleton/history"))]
+    UnknownHistoryEntry(String),
+
     #[error("{0}")]
     #[diagnostic(code(skel::other_error))]
     Other(String),
@@ -30,7 +104,7 @@ pub struct ConfigError {
     pub span: SourceSpan,
     pub label: Option<&'static str>,
     #[help]
-    pub help: Option<&'static str>,
+    pub help: Option<String>,
     pub kind: ConfigErrorKind,
 }
 
@@ -51,6 +125,42 @@ pub enum ConfigErrorKind {
     #[error("invalid float")]
     #[diagnostic(code(skel::config::invalid_float))]
     InvalidFloat,
+
+    #[error("unknown dependency: {0}")]
+    #[diagnostic(code(skel::config::unknown_dependency))]
+    UnknownDependency(String),
+
+    #[error("dependency loop detected: {}", .0.join(" -> "))]
+    #[diagnostic(code(skel::config::dependency_loop))]
+    DependencyLoop(Vec<String>),
+
+    #[error("invalid content kind: {0}")]
+    #[diagnostic(code(skel::config::invalid_content_kind))]
+    InvalidContentKind(String),
+
+    #[error("invalid policy: {0}")]
+    #[diagnostic(code(skel::config::invalid_policy))]
+    InvalidPolicy(String),
+
+    #[error("invalid mode: {0}")]
+    #[diagnostic(code(skel::config::invalid_mode))]
+    InvalidMode(String),
+
+    #[error("{0} can't be set on {} content", .1.name())]
+    #[diagnostic(code(skel::config::unsupported_option))]
+    UnsupportedOption(String, ContentKind),
+
+    #[error("archives can't be checked out at a ref")]
+    #[diagnostic(code(skel::config::archive_ref))]
+    ArchiveRef,
+
+    #[error("invalid glob: {0}")]
+    #[diagnostic(code(skel::config::invalid_glob))]
+    InvalidGlob(String),
+
+    #[error("skeleton extends itself: {}", .0.join(" -> "))]
+    #[diagnostic(code(skel::config::extends_loop))]
+    ExtendsLoop(Vec<String>),
 }
 
 impl ConfigError {
@@ -68,19 +178,22 @@ impl ConfigError {
         Self {
             config: proposed_doc,
             span: inserted_entry.span().to_owned(),
-            help: Some("this node requires an argument"),
+            help: Some("this node requires an argument".to_owned()),
             label: Some("insert an argument here"),
             kind: ConfigErrorKind::MissingArgument,
         }
     }
 
-    pub fn from_invalid_string_argument(doc: &KdlDocument, node: &KdlNode, index: usize) -> Self {
-        let span = node.get(index).unwrap().span().to_owned();
+    pub fn from_invalid_string_argument<K>(doc: &KdlDocument, node: &KdlNode, key: K) -> Self
+    where
+        K: Into<NodeKey>,
+    {
+        let span = node.get(key).unwrap().span().to_owned();
 
         Self {
             config: doc.to_string(),
             span,
-            help: Some("the indicated argument must be a string"),
+            help: Some("the indicated argument must be a string".to_owned()),
             label: None,
             kind: ConfigErrorKind::InvalidString,
         }
@@ -90,9 +203,128 @@ impl ConfigError {
         Self {
             config: doc.to_string(),
             span: node.get(0).unwrap().span().to_owned(),
-            help: Some("the file indicated does not exist"),
+            help: Some("the file indicated does not exist".to_owned()),
             label: None,
             kind: ConfigErrorKind::MissingSource,
         }
     }
+
+    pub fn from_unknown_dependency(doc: &KdlDocument, entry: &KdlEntry, dependency: &str) -> Self {
+        Self {
+            config: doc.to_string(),
+            span: entry.span().to_owned(),
+            help: Some("dependencies must name another content path in the skeleton".to_owned()),
+            label: Some("no content with this path"),
+            kind: ConfigErrorKind::UnknownDependency(dependency.to_owned()),
+        }
+    }
+
+    pub fn from_dependency_loop(doc: &KdlDocument, entry: &KdlEntry, path: Vec<String>) -> Self {
+        Self {
+            config: doc.to_string(),
+            span: entry.span().to_owned(),
+            help: Some("remove one of the dependencies in the loop".to_owned()),
+            label: Some("this dependency closes the loop"),
+            kind: ConfigErrorKind::DependencyLoop(path),
+        }
+    }
+
+    /// A dependency loop made only of dependencies the parent skeleton
+    /// declared, with this skeleton's content closing it.
+    pub fn from_inherited_dependency_loop(doc: &KdlDocument, extends: Option<&KdlNode>, path: Vec<String>) -> Self {
+        let span = extends
+            .and_then(|node| node.get(0))
+            .map(|entry| entry.span().to_owned())
+            .unwrap_or_else(|| (0, 0).into());
+
+        Self {
+            config: doc.to_string(),
+            span,
+            help: Some("remove one of the dependencies in the loop".to_owned()),
+            label: Some("the loop goes through the extended skeleton"),
+            kind: ConfigErrorKind::DependencyLoop(path),
+        }
+    }
+
+    pub fn from_invalid_content_kind(doc: &KdlDocument, node: &KdlNode, kind: &str) -> Self {
+        let help = match did_you_mean(kind, &ContentKind::NAMES) {
+            Some(suggestion) => format!("did you mean \"{}\"?", suggestion),
+            None => format!("valid kinds are \"{}\"", ContentKind::NAMES.join("\", \"")),
+        };
+
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some(help),
+            label: Some("unknown kind"),
+            kind: ConfigErrorKind::InvalidContentKind(kind.to_owned()),
+        }
+    }
+
+    pub fn from_invalid_policy(doc: &KdlDocument, node: &KdlNode, policy: &str) -> Self {
+        let help = match did_you_mean(policy, &Policy::NAMES) {
+            Some(suggestion) => format!("did you mean \"{}\"?", suggestion),
+            None => format!("valid policies are \"{}\"", Policy::NAMES.join("\", \"")),
+        };
+
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some(help),
+            label: Some("unknown policy"),
+            kind: ConfigErrorKind::InvalidPolicy(policy.to_owned()),
+        }
+    }
+
+    pub fn from_invalid_mode(doc: &KdlDocument, node: &KdlNode, mode: &str) -> Self {
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some("modes are permissions in octal, like \"0755\"".to_owned()),
+            label: Some("invalid mode"),
+            kind: ConfigErrorKind::InvalidMode(mode.to_owned()),
+        }
+    }
+
+    pub fn from_unsupported_option(doc: &KdlDocument, node: &KdlNode, kind: &ContentKind, help: &str) -> Self {
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some(help.to_owned()),
+            label: Some("not used by this kind"),
+            kind: ConfigErrorKind::UnsupportedOption(node.name().value().to_owned(), kind.clone()),
+        }
+    }
+
+    pub fn from_archive_ref(doc: &KdlDocument, node: &KdlNode) -> Self {
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some("an archive is a single version, remove the ref or point at a git repository".to_owned()),
+            label: Some("ref given for an archive"),
+            kind: ConfigErrorKind::ArchiveRef,
+        }
+    }
+
+    pub fn from_extends_loop(doc: &KdlDocument, node: &KdlNode, chain: Vec<String>) -> Self {
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some("a skeleton can't extend itself, directly or through its parents".to_owned()),
+            label: Some("this skeleton is already being extended"),
+            kind: ConfigErrorKind::ExtendsLoop(chain),
+        }
+    }
+
+    pub fn from_invalid_glob(doc: &KdlDocument, node: &KdlNode, err: &globset::Error) -> Self {
+        let glob = err.glob().unwrap_or_default();
+
+        Self {
+            config: doc.to_string(),
+            span: node.get(0).unwrap().span().to_owned(),
+            help: Some(err.kind().to_string()),
+            label: Some("invalid glob"),
+            kind: ConfigErrorKind::InvalidGlob(glob.to_owned()),
+        }
+    }
 }
@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use kdl::{KdlDocument, KdlEntry, KdlNode};
+
+use crate::content::parse_mode;
+use crate::error::SkelError;
+use crate::plan::Existing;
+use crate::transaction::Transaction;
+use crate::util::file_mode;
+
+const MANIFEST: &str = "manifest.kdl";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UndoResult {
+    pub destination: PathBuf,
+    pub status: UndoStatus,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UndoStatus {
+    Restored,
+    Removed,
+}
+
+impl fmt::Display for UndoStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            UndoStatus::Restored => "restored",
+            UndoStatus::Removed => "removed",
+        };
+
+        f.pad(status)
+    }
+}
+
+/// Where every apply that changed the project keeps what it replaced, one
+/// directory per apply named after when it happened.
+pub fn history_dir(project: &Path) -> PathBuf {
+    project.join(".skeleton/history")
+}
+
+/// Commits a transaction that changes `destinations`, relative to the
+/// project, after saving what is there now into a new history entry. The
+/// entry is dropped again if the commit fails.
+pub fn commit_with_history(project: &Path, transaction: Transaction, destinations: &[PathBuf]) -> Result<(), SkelError> {
+    let entry = if destinations.is_empty() {
+        None
+    } else {
+        Some(record(project, destinations)?)
+    };
+
+    if let Err(err) = transaction.commit() {
+        if let Some(entry) = entry {
+            let _ = fs::remove_dir_all(entry);
+        }
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Copies each existing destination into `files/` of a new entry, and lists
+/// in its manifest which files to restore and which to remove on undo.
+/// Symlinks are listed along with their target instead of being copied,
+/// and files along with their permissions.
+fn record(project: &Path, destinations: &[PathBuf]) -> Result<PathBuf, SkelError> {
+    let history = history_dir(project);
+    fs::create_dir_all(&history)?;
+    let entry = new_entry(&history)?;
+
+    let mut manifest = KdlDocument::new();
+    for destination in destinations {
+        let path = project.join(destination);
+        let (node_name, property) = match Existing::read(&path)? {
+            Some(Existing::File(data)) => {
+                let backup = entry.join("files").join(destination);
+                fs::create_dir_all(backup.parent().unwrap())?;
+                fs::write(backup, data)?;
+                let mode = file_mode(&path)?.map(|mode| KdlEntry::new_prop("mode", format!("{:04o}", mode)));
+                ("restore", mode)
+            }
+            Some(Existing::Symlink(target)) => {
+                let target = String::from_utf8_lossy(&target).into_owned();
+                ("link", Some(KdlEntry::new_prop("target", target)))
+            }
+            None => ("remove", None),
+        };
+
+        let mut node = KdlNode::new(node_name);
+        node.push(destination.to_string_lossy().into_owned());
+        if let Some(property) = property {
+            node.push(property);
+        }
+        manifest.nodes_mut().push(node);
+    }
+
+    fs::write(entry.join(MANIFEST), manifest.to_string())?;
+
+    Ok(entry)
+}
+
+/// Creates the directory for a new entry, named after the current UTC time.
+fn new_entry(history: &Path) -> Result<PathBuf, SkelError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let timestamp = format_timestamp(now.as_secs());
+
+    let mut attempt = 1;
+    loop {
+        let name = match attempt {
+            1 => timestamp.clone(),
+            _ => format!("{}-{}", timestamp, attempt),
+        };
+
+        let entry = history.join(name);
+        match fs::create_dir(&entry) {
+            Ok(()) => return Ok(entry),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Formats seconds since the epoch as e.g. 20240131T235959Z.
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let seconds = secs % 86400;
+
+    // civil date from days since 1970-01-01, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    )
+}
+
+/// The names of every history entry, oldest first.
+pub fn entries(project: &Path) -> Result<Vec<String>, SkelError> {
+    let mut entries: Vec<String> = match fs::read_dir(history_dir(project)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join(MANIFEST).is_file())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+    entries.sort();
+
+    Ok(entries)
+}
+
+/// Puts the project back the way it was before the last apply, or before
+/// the named one. Newer applies are undone along with the named one, all in
+/// one transaction, and their entries are removed afterwards.
+pub fn undo(project: &Path, name: Option<&str>) -> Result<Vec<UndoResult>, SkelError> {
+    let entries = entries(project)?;
+    let index = match name {
+        Some(name) => match entries.iter().position(|entry| entry == name) {
+            Some(index) => index,
+            None => return Err(SkelError::UnknownHistoryEntry(name.to_owned())),
+        },
+        None => match entries.len() {
+            0 => return Err(SkelError::NothingToUndo),
+            len => len - 1,
+        },
+    };
+
+    // undoing newest first means the oldest entry that touched a file
+    // decides what it goes back to
+    let mut recorded: BTreeMap<PathBuf, (PathBuf, KdlNode)> = BTreeMap::new();
+    for name in entries[index..].iter().rev() {
+        let entry = history_dir(project).join(name);
+        let manifest: KdlDocument = fs::read_to_string(entry.join(MANIFEST))?.parse()?;
+        for node in manifest.nodes() {
+            if let Some(destination) = node.get(0).and_then(|entry| entry.value().as_string()) {
+                recorded.insert(PathBuf::from(destination), (entry.clone(), node.clone()));
+            }
+        }
+    }
+
+    let mut transaction = Transaction::new();
+    let mut results: Vec<UndoResult> = Vec::new();
+    for (destination, (entry, node)) in recorded {
+        let status = match node.name().value() {
+            "restore" => {
+                let data = fs::read(entry.join("files").join(&destination))?;
+                let mode = node.get("mode").and_then(|entry| entry.value().as_string()).and_then(parse_mode);
+                transaction.write(&project.join(&destination), &data, mode)?;
+                UndoStatus::Restored
+            }
+            "link" => {
+                let target = match node.get("target").and_then(|entry| entry.value().as_string()) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                transaction.symlink(&project.join(&destination), target)?;
+                UndoStatus::Restored
+            }
+            "remove" => {
+                transaction.delete(&project.join(&destination));
+                UndoStatus::Removed
+            }
+            _ => continue,
+        };
+
+        results.push(UndoResult { destination, status });
+    }
+    transaction.commit()?;
+
+    for newer in &entries[index..] {
+        fs::remove_dir_all(history_dir(project).join(newer))?;
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::apply::{apply, apply_plan};
+    use crate::plan::Plan;
+    use crate::test_helpers::{load, project_with_content};
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(format_timestamp(0), "19700101T000000Z");
+        assert_eq!(format_timestamp(951782400), "20000229T000000Z");
+        assert_eq!(format_timestamp(1706745599), "20240131T235959Z");
+    }
+
+    #[test]
+    fn records_only_applies_that_change_something() {
+        let project = project_with_content(&[("one.txt", "one")]);
+        apply(&load(&project)).unwrap();
+        apply(&load(&project)).unwrap();
+
+        assert_eq!(entries(project.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn undoes_the_last_apply() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two")]);
+        apply(&load(&project)).unwrap();
+        let lockfile = fs::read_to_string(project.path().join(".skeleton.lock")).unwrap();
+
+        fs::write(project.path().join(".skeleton/content/one.txt"), "changed").unwrap();
+        fs::remove_file(project.path().join(".skeleton/content/two.txt")).unwrap();
+        fs::write(project.path().join(".skeleton/content/three.txt"), "three").unwrap();
+        let skeleton = load(&project);
+        apply_plan(&skeleton, Plan::from_skeleton_with_prune(&skeleton, true).unwrap()).unwrap();
+        assert!(!project.path().join("two.txt").exists());
+
+        let mut results = undo(&skeleton.project, None).unwrap();
+        results.sort_by(|a, b| a.destination.cmp(&b.destination));
+        assert_eq!(results, vec![
+            UndoResult { destination: PathBuf::from(".skeleton.lock"), status: UndoStatus::Restored },
+            UndoResult { destination: PathBuf::from("one.txt"), status: UndoStatus::Restored },
+            UndoResult { destination: PathBuf::from("three.txt"), status: UndoStatus::Removed },
+            UndoResult { destination: PathBuf::from("two.txt"), status: UndoStatus::Restored },
+        ]);
+        assert_eq!(fs::read_to_string(project.path().join("one.txt")).unwrap(), "one");
+        assert_eq!(fs::read_to_string(project.path().join("two.txt")).unwrap(), "two");
+        assert!(!project.path().join("three.txt").exists());
+        assert_eq!(fs::read_to_string(project.path().join(".skeleton.lock")).unwrap(), lockfile);
+        assert_eq!(entries(project.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn undoes_a_named_apply_and_everything_after_it() {
+        let project = project_with_content(&[("one.txt", "one")]);
+        apply(&load(&project)).unwrap();
+        fs::write(project.path().join(".skeleton/content/two.txt"), "two").unwrap();
+        apply(&load(&project)).unwrap();
+
+        let first = entries(project.path()).unwrap().remove(0);
+        undo(project.path(), Some(&first)).unwrap();
+        assert!(!project.path().join("one.txt").exists());
+        assert!(!project.path().join("two.txt").exists());
+        assert!(!project.path().join(".skeleton.lock").exists());
+        assert_eq!(entries(project.path()).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn restores_symlinks() {
+        let project = project_with_content(&[("symlink_config", "one")]);
+        apply(&load(&project)).unwrap();
+        fs::write(project.path().join(".skeleton/content/symlink_config"), "two").unwrap();
+        apply(&load(&project)).unwrap();
+
+        let results = undo(project.path(), None).unwrap();
+        assert!(results.contains(&UndoResult { destination: PathBuf::from("config"), status: UndoStatus::Restored }));
+        assert_eq!(fs::read_link(project.path().join("config")).unwrap(), PathBuf::from("one"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn restores_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = project_with_content(&[("build.sh", "build")]);
+        apply(&load(&project)).unwrap();
+        fs::set_permissions(project.path().join("build.sh"), fs::Permissions::from_mode(0o600)).unwrap();
+        fs::write(project.path().join(".skeleton/content/build.sh"), "changed").unwrap();
+        apply(&load(&project)).unwrap();
+
+        undo(project.path(), None).unwrap();
+        let metadata = fs::metadata(project.path().join("build.sh")).unwrap();
+        assert_eq!(fs::read_to_string(project.path().join("build.sh")).unwrap(), "build");
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn errors_without_history() {
+        let project = project_with_content(&[("one.txt", "one")]);
+        assert!(matches!(undo(project.path(), None).unwrap_err(), SkelError::NothingToUndo));
+        assert!(matches!(
+            undo(project.path(), Some("yesterday")).unwrap_err(),
+            SkelError::UnknownHistoryEntry(name) if name == "yesterday"
+        ));
+    }
+}
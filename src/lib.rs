@@ -1,8 +1,22 @@
 pub mod skeleton;
 pub use crate::skeleton::Skeleton;
 
+pub mod apply;
 pub mod config;
 pub mod content;
+pub mod diff;
 pub mod error;
+pub mod history;
+pub mod merge;
+pub mod plan;
+pub mod render;
+pub mod runner;
+pub mod source;
+pub mod transaction;
+pub mod update;
+pub mod verify;
 
 pub mod util;
+
+#[cfg(test)]
+mod test_helpers;
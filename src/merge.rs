@@ -0,0 +1,177 @@
+use std::ops::Range;
+
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+
+const OURS_MARKER: &str = "<<<<<<< project\n";
+const SEPARATOR: &str = "=======\n";
+const THEIRS_MARKER: &str = ">>>>>>> skeleton\n";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Merge {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/// A change one side made to the base: the base lines in `base` were
+/// replaced by `lines`.
+#[derive(Clone, Debug)]
+struct Hunk<'a> {
+    base: Range<usize>,
+    lines: Vec<&'a str>,
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs`,
+/// line by line. Changes that touch the same lines in different ways are
+/// kept as conflicts between markers, with the project's lines first.
+pub fn merge(base: &str, ours: &str, theirs: &str) -> Merge {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let our_hunks = hunks(&base_lines, ours);
+    let their_hunks = hunks(&base_lines, theirs);
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let mut position = 0;
+    let (mut o, mut t) = (0, 0);
+
+    while o < our_hunks.len() || t < their_hunks.len() {
+        // start with whichever change comes first, then keep pulling in
+        // changes from either side until none overlap the region
+        let first = match (our_hunks.get(o), their_hunks.get(t)) {
+            (Some(ours), Some(theirs)) => ours.base.start.min(theirs.base.start),
+            (Some(ours), None) => ours.base.start,
+            (None, Some(theirs)) => theirs.base.start,
+            (None, None) => unreachable!(),
+        };
+        let mut region = first..first;
+        let (o_start, t_start) = (o, t);
+        loop {
+            if let Some(hunk) = our_hunks.get(o).filter(|hunk| hunk.base.start <= region.end) {
+                region.end = region.end.max(hunk.base.end);
+                o += 1;
+            } else if let Some(hunk) = their_hunks.get(t).filter(|hunk| hunk.base.start <= region.end) {
+                region.end = region.end.max(hunk.base.end);
+                t += 1;
+            } else {
+                break;
+            }
+        }
+
+        text.extend(base_lines[position..region.start].iter().copied());
+        position = region.end;
+
+        let ours = apply(&base_lines, &region, &our_hunks[o_start..o]);
+        let theirs = apply(&base_lines, &region, &their_hunks[t_start..t]);
+        if o == o_start {
+            text.extend(theirs);
+        } else if t == t_start || ours == theirs {
+            text.extend(ours);
+        } else {
+            conflicts += 1;
+            text.push_str(OURS_MARKER);
+            push_lines(&mut text, &ours);
+            text.push_str(SEPARATOR);
+            push_lines(&mut text, &theirs);
+            text.push_str(THEIRS_MARKER);
+        }
+    }
+
+    text.extend(base_lines[position..].iter().copied());
+
+    Merge { text, conflicts }
+}
+
+fn hunks<'a>(base_lines: &[&str], other: &'a str) -> Vec<Hunk<'a>> {
+    let other_lines: Vec<&str> = other.split_inclusive('\n').collect();
+    let mut hunks: Vec<Hunk> = Vec::new();
+
+    for op in capture_diff_slices(Algorithm::Myers, base_lines, &other_lines) {
+        let (tag, base, other) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+
+        // a deletion directly followed by an insertion is one replacement
+        match hunks.last_mut() {
+            Some(last) if last.base.end == base.start && tag == DiffTag::Insert => {
+                last.lines.extend(&other_lines[other]);
+            }
+            _ => hunks.push(Hunk {
+                base,
+                lines: other_lines[other].to_vec(),
+            }),
+        }
+    }
+
+    hunks
+}
+
+/// One side's version of the base lines in `region`.
+fn apply<'a>(base_lines: &[&'a str], region: &Range<usize>, hunks: &[Hunk<'a>]) -> Vec<&'a str> {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut position = region.start;
+
+    for hunk in hunks {
+        lines.extend(&base_lines[position..hunk.base.start]);
+        lines.extend(&hunk.lines);
+        position = hunk.base.end;
+    }
+    lines.extend(&base_lines[position..region.end]);
+
+    lines
+}
+
+/// Conflict markers have to start on their own line, even when the last
+/// line of a side has no newline.
+fn push_lines(text: &mut String, lines: &[&str]) {
+    text.extend(lines.iter().copied());
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_changes_from_both_sides() {
+        let result = merge("one\ntwo\nthree\nfour\n", "ONE\ntwo\nthree\nfour\n", "one\ntwo\nthree\nFOUR\n");
+        assert_eq!(result, Merge { text: "ONE\ntwo\nthree\nFOUR\n".to_owned(), conflicts: 0 });
+    }
+
+    #[test]
+    fn keeps_insertions_and_deletions() {
+        let result = merge("one\ntwo\nthree\nfour\n", "zero\none\ntwo\nthree\nfour\n", "one\ntwo\nfour\nfive\n");
+        assert_eq!(result, Merge { text: "zero\none\ntwo\nfour\nfive\n".to_owned(), conflicts: 0 });
+    }
+
+    #[test]
+    fn accepts_the_same_change_on_both_sides() {
+        let result = merge("one\ntwo\n", "one\n2\n", "one\n2\n");
+        assert_eq!(result, Merge { text: "one\n2\n".to_owned(), conflicts: 0 });
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        let result = merge("one\ntwo\nthree\n", "one\nproject\nthree\n", "one\nskeleton\nthree\n");
+        assert_eq!(result, Merge {
+            text: "one\n<<<<<<< project\nproject\n=======\nskeleton\n>>>>>>> skeleton\nthree\n".to_owned(),
+            conflicts: 1,
+        });
+    }
+
+    #[test]
+    fn ends_conflicting_lines_before_markers() {
+        let result = merge("one", "project", "skeleton");
+        assert_eq!(result.text, "<<<<<<< project\nproject\n=======\nskeleton\n>>>>>>> skeleton\n");
+    }
+
+    #[test]
+    fn conflicts_everywhere_without_a_base() {
+        let result = merge("", "same\nproject\n", "same\nskeleton\n");
+        assert_eq!(result, Merge {
+            text: "<<<<<<< project\nsame\nproject\n=======\nsame\nskeleton\n>>>>>>> skeleton\n".to_owned(),
+            conflicts: 1,
+        });
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tera::{Context, Value};
+
+use crate::config::Lockfile;
+use crate::content::{Content, ContentKind, Policy};
+use crate::error::SkelError;
+use crate::render::Renderer;
+use crate::skeleton::Skeleton;
+use crate::source;
+use crate::util::file_mode;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    pub action: Action,
+    pub destination: PathBuf,
+    pub data: Vec<u8>,
+    pub kind: ContentKind,
+    pub mode: Option<u32>,
+    pub policy: Policy,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    Create,
+    Overwrite,
+    /// Only the permissions differ
+    Chmod,
+    Skip,
+    Delete,
+    /// Dropped from the skeleton, but kept because pruning is off
+    Orphan,
+    /// Dropped from the skeleton, but kept because the project changed it
+    Modified,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Action::Create => "create",
+            Action::Overwrite => "overwrite",
+            Action::Chmod => "chmod",
+            Action::Skip => "skip",
+            Action::Delete => "delete",
+            Action::Orphan => "orphan",
+            Action::Modified => "modified",
+        };
+
+        f.pad(action)
+    }
+}
+
+/// What is at a destination in the project now. A symlink is read as its
+/// target, the way symlink content renders, rather than followed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Existing {
+    File(Vec<u8>),
+    Symlink(Vec<u8>),
+}
+
+impl Existing {
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(path)?;
+            Ok(Some(Existing::Symlink(target.to_string_lossy().into_owned().into_bytes())))
+        } else {
+            Ok(Some(Existing::File(fs::read(path)?)))
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Existing::File(data) | Existing::Symlink(data) => data,
+        }
+    }
+
+    /// Whether content of `kind` rendering to `data` is already in place.
+    pub fn matches(&self, kind: &ContentKind, data: &[u8]) -> bool {
+        match (self, kind) {
+            (Existing::Symlink(target), ContentKind::Symlink) => target == data,
+            (Existing::File(existing), ContentKind::File | ContentKind::Template) => existing == data,
+            _ => false,
+        }
+    }
+
+    fn kind(&self) -> ContentKind {
+        match self {
+            Existing::File(_) => ContentKind::File,
+            Existing::Symlink(_) => ContentKind::Symlink,
+        }
+    }
+}
+
+impl Plan {
+    /// Plans an apply that keeps files dropped from the skeleton.
+    pub fn from_skeleton(skeleton: &Skeleton) -> Result<Self, SkelError> {
+        Self::from_skeleton_with_prune(skeleton, false)
+    }
+
+    /// Renders every piece of content, in dependency order, and compares it
+    /// against the project to decide what apply would do. Files the lockfile
+    /// says were written before but that the skeleton no longer produces are
+    /// planned at the end, unless the skeleton still knows them as ignored
+    /// content or content switched off by its condition. They are deleted
+    /// when `prune` is set, but never once the project changed them. Nothing
+    /// is written.
+    pub fn from_skeleton_with_prune(skeleton: &Skeleton, prune: bool) -> Result<Self, SkelError> {
+        let renderer = Renderer::new(skeleton)?;
+        let mut steps: Vec<Step> = Vec::new();
+        let mut sources: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut unmanaged: HashSet<PathBuf> = HashSet::new();
+
+        for content in skeleton.calculate() {
+            for variables in instance_variables(skeleton, &content)? {
+                let relative_destination = renderer.render_destination(&content.destination, &variables)?;
+
+                let included = content.policy != Policy::Ignore
+                    && match &content.when {
+                        Some(condition) => renderer.evaluate_condition(condition, &variables)?,
+                        None => true,
+                    };
+                if !included {
+                    unmanaged.insert(relative_destination);
+                    continue;
+                }
+
+                let data = renderer.render(&content, &variables)?;
+                if let Some(existing) = sources.insert(relative_destination.clone(), content.source.clone()) {
+                    return Err(SkelError::DestinationConflict(
+                        relative_destination,
+                        existing,
+                        content.source,
+                    ));
+                }
+
+                let destination = skeleton.project.join(&relative_destination);
+                let mode = renderer.mode(&content)?;
+                let current_mode = file_mode(&destination)?;
+
+                let action = match Existing::read(&destination)? {
+                    None => Action::Create,
+                    // the project owns create-only content once it exists
+                    Some(_) if content.policy == Policy::CreateOnly => Action::Skip,
+                    Some(existing) if !existing.matches(&content.kind, &data) => Action::Overwrite,
+                    Some(_) if mode.is_some() && mode != current_mode => Action::Chmod,
+                    Some(_) => Action::Skip,
+                };
+
+                steps.push(Step {
+                    action,
+                    destination: relative_destination,
+                    data,
+                    kind: content.kind.clone(),
+                    mode,
+                    policy: content.policy.clone(),
+                });
+            }
+        }
+
+        if let Some(lockfile) = Lockfile::read_from(&skeleton.lockfile, &skeleton.project)? {
+            for (destination, hash) in &lockfile.content {
+                if sources.contains_key(destination) || unmanaged.contains(destination) {
+                    continue;
+                }
+
+                // the current contents are kept around to show what goes away
+                if let Some(existing) = Existing::read(&skeleton.project.join(destination))? {
+                    let action = if source::hash(existing.data()) != *hash {
+                        Action::Modified
+                    } else if prune {
+                        Action::Delete
+                    } else {
+                        Action::Orphan
+                    };
+
+                    steps.push(Step {
+                        action,
+                        destination: destination.clone(),
+                        kind: existing.kind(),
+                        data: existing.data().to_vec(),
+                        mode: None,
+                        policy: Policy::Overwrite,
+                    });
+                }
+            }
+        }
+
+        Ok(Self { steps })
+    }
+}
+
+/// The variables to render each output of a piece of content with. Content
+/// using for_each gets one set per list item, everything else exactly one.
+fn instance_variables(skeleton: &Skeleton, content: &Content) -> Result<Vec<Context>, SkelError> {
+    let for_each = match &content.for_each {
+        Some(for_each) => for_each,
+        None => return Ok(vec![skeleton.variables.clone()]),
+    };
+
+    let items = match skeleton.variables.get(&for_each.variable) {
+        Some(Value::Array(items)) => items,
+        _ => return Err(SkelError::InvalidForEach(for_each.variable.clone())),
+    };
+
+    let instances = items
+        .iter()
+        .map(|item| {
+            let mut variables = skeleton.variables.clone();
+            variables.insert(&for_each.item, item);
+            variables
+        })
+        .collect();
+
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    use crate::test_helpers::project_with_content;
+
+    /// Files written by these tests get their permissions from the umask.
+    fn written_mode() -> Option<u32> {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("file"), "").unwrap();
+        file_mode(&dir.path().join("file")).unwrap()
+    }
+
+    #[test]
+    fn plans_without_writing() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two"), ("three.txt", "three")]);
+
+        fs::write(project.path().join("two.txt"), "changed").unwrap();
+        fs::write(project.path().join("three.txt"), "three").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Create, destination: PathBuf::from("one.txt"), data: b"one".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::Overwrite },
+            Step { action: Action::Skip, destination: PathBuf::from("three.txt"), data: b"three".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::Overwrite },
+            Step { action: Action::Overwrite, destination: PathBuf::from("two.txt"), data: b"two".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::Overwrite },
+        ]);
+
+        assert!(!project.path().join("one.txt").exists());
+        assert_eq!(fs::read_to_string(project.path().join("two.txt")).unwrap(), "changed");
+    }
+
+    #[test]
+    fn renders_destinations() {
+        let project = project_with_content(&[("src/{{ crate_name }}/lib.rs", "lib")]);
+        fs::write(project.path().join(".skeleton.kdl"), r#"
+            variables {
+                crate_name "skel"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Create, destination: PathBuf::from("src/skel/lib.rs"), data: b"lib".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::Overwrite },
+        ]);
+    }
+
+    #[test]
+    fn renders_each_item_of_for_each() {
+        let project = project_with_content(&[("{{ svc.name }}.yaml", "port: {{ svc.port }}")]);
+        let skeleton_root = project.path().join(".skeleton");
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "{{ svc.name }}.yaml" {
+                kind "template"
+                for_each "services" as="svc"
+            }
+        "#).unwrap();
+        fs::write(project.path().join(".skeleton.kdl"), r#"
+            variables {
+                services {
+                    - name="api" port=80
+                    - name="worker" port=8080
+                }
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Create, destination: PathBuf::from("api.yaml"), data: b"port: 80".to_vec(), kind: ContentKind::Template, mode: written_mode(), policy: Policy::Overwrite },
+            Step { action: Action::Create, destination: PathBuf::from("worker.yaml"), data: b"port: 8080".to_vec(), kind: ContentKind::Template, mode: written_mode(), policy: Policy::Overwrite },
+        ]);
+    }
+
+    #[test]
+    fn skips_content_when_condition_is_false() {
+        let project = project_with_content(&[("Dockerfile", ""), ("{{ svc }}.yaml", "")]);
+        let skeleton_root = project.path().join(".skeleton");
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "Dockerfile" {
+                when "{{ use_docker }}"
+            }
+            content "{{ svc }}.yaml" {
+                for_each "services" as="svc"
+                when "svc != \"worker\""
+            }
+        "#).unwrap();
+        fs::write(project.path().join(".skeleton.kdl"), r#"
+            variables {
+                use_docker false
+                services {
+                    - "api"
+                    - "worker"
+                }
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Create, destination: PathBuf::from("api.yaml"), data: vec![], kind: ContentKind::File, mode: written_mode(), policy: Policy::Overwrite },
+        ]);
+    }
+
+    #[test]
+    fn follows_content_policies() {
+        let project = project_with_content(&[("README.md", "readme"), ("LICENSE", "license"), ("CHANGELOG.md", "changelog")]);
+        let skeleton_root = project.path().join(".skeleton");
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "README.md" {
+                policy "create_only"
+            }
+            content "LICENSE" {
+                policy "create_only"
+            }
+            content "CHANGELOG.md" {
+                policy "ignore"
+            }
+        "#).unwrap();
+        fs::write(project.path().join("README.md"), "changed").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Create, destination: PathBuf::from("LICENSE"), data: b"license".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::CreateOnly },
+            Step { action: Action::Skip, destination: PathBuf::from("README.md"), data: b"readme".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::CreateOnly },
+        ]);
+    }
+
+    #[test]
+    fn plans_deletions_for_files_dropped_from_the_skeleton() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two"), ("three.txt", "three"), ("four.txt", "four")]);
+        let content_root = project.path().join(".skeleton/content");
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        crate::apply::apply(&skeleton).unwrap();
+
+        fs::remove_file(content_root.join("two.txt")).unwrap();
+        fs::remove_file(content_root.join("three.txt")).unwrap();
+        fs::remove_file(content_root.join("four.txt")).unwrap();
+        // already gone from the project, so there is nothing to delete
+        fs::remove_file(project.path().join("three.txt")).unwrap();
+        fs::write(project.path().join("four.txt"), "changed").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton_with_prune(&skeleton, true).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Skip, destination: PathBuf::from("one.txt"), data: b"one".to_vec(), kind: ContentKind::File, mode: written_mode(), policy: Policy::Overwrite },
+            Step { action: Action::Modified, destination: PathBuf::from("four.txt"), data: b"changed".to_vec(), kind: ContentKind::File, mode: None, policy: Policy::Overwrite },
+            Step { action: Action::Delete, destination: PathBuf::from("two.txt"), data: b"two".to_vec(), kind: ContentKind::File, mode: None, policy: Policy::Overwrite },
+        ]);
+
+        let actions: Vec<Action> = Plan::from_skeleton(&skeleton).unwrap().steps.into_iter().map(|step| step.action).collect();
+        assert_eq!(actions, vec![Action::Skip, Action::Modified, Action::Orphan]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn plans_permission_changes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = project_with_content(&[("executable_build.sh", "build"), ("test.sh", "test")]);
+        let content_root = project.path().join(".skeleton/content");
+        fs::set_permissions(content_root.join("test.sh"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        fs::write(project.path().join("build.sh"), "build").unwrap();
+        fs::set_permissions(project.path().join("build.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(project.path().join("test.sh"), "test").unwrap();
+        fs::set_permissions(project.path().join("test.sh"), fs::Permissions::from_mode(0o700)).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![
+            Step { action: Action::Chmod, destination: PathBuf::from("build.sh"), data: b"build".to_vec(), kind: ContentKind::File, mode: Some(0o755), policy: Policy::Overwrite },
+            Step { action: Action::Skip, destination: PathBuf::from("test.sh"), data: b"test".to_vec(), kind: ContentKind::File, mode: Some(0o700), policy: Policy::Overwrite },
+        ]);
+    }
+
+    #[test]
+    fn leaves_ignored_and_disabled_content_alone() {
+        let project = project_with_content(&[("Dockerfile", "docker"), ("CHANGELOG.md", "changelog")]);
+        let skeleton_root = project.path().join(".skeleton");
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        crate::apply::apply(&skeleton).unwrap();
+
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "Dockerfile" {
+                when "use_docker"
+            }
+            content "CHANGELOG.md" {
+                policy "ignore"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let plan = Plan::from_skeleton(&skeleton).unwrap();
+        assert_eq!(plan.steps, vec![]);
+    }
+
+    #[test]
+    fn errors_when_destinations_conflict() {
+        let project = project_with_content(&[("one", ""), ("two", "")]);
+        let skeleton_root = project.path().join(".skeleton");
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "two" {
+                destination "one"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let result = Plan::from_skeleton(&skeleton);
+        assert!(matches!(
+            result.unwrap_err(),
+            SkelError::DestinationConflict(destination, _, _) if destination == std::path::Path::new("one")
+        ));
+    }
+
+    #[test]
+    fn errors_when_for_each_is_not_a_list() {
+        let project = project_with_content(&[("one", "")]);
+        let skeleton_root = project.path().join(".skeleton");
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "one" {
+                for_each "services"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let result = Plan::from_skeleton(&skeleton);
+        assert!(matches!(result.unwrap_err(), SkelError::InvalidForEach(name) if name == "services"));
+    }
+}
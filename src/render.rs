@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use tera::{Context, Tera};
+
+use crate::content::{Content, ContentKind};
+use crate::error::SkelError;
+use crate::skeleton::Skeleton;
+use crate::util::file_mode;
+
+#[derive(Debug)]
+pub struct Renderer {
+    pub root: PathBuf,
+    /// One Tera instance per skeleton
+    teras: Vec<Tera>,
+    /// The Tera instance and template name of each template source
+    names: HashMap<PathBuf, (usize, String)>,
+}
+
+impl Renderer {
+    /// Loads the templates of each skeleton into a Tera instance of its own,
+    /// named by their path in that skeleton's content tree, so templates can
+    /// include and extend each other wherever the skeleton is mounted.
+    pub fn new(skeleton: &Skeleton) -> Result<Self, SkelError> {
+        let root = skeleton.content_root();
+
+        let mut templates: Vec<Vec<(PathBuf, Option<String>)>> = vec![Vec::new(); skeleton.sources.len().max(1)];
+        let mut names: HashMap<PathBuf, (usize, String)> = HashMap::new();
+        for (key, content) in &skeleton.content {
+            if content.kind != ContentKind::Template {
+                continue;
+            }
+
+            let (index, name) = skeleton.origins.get(key).cloned().unwrap_or_else(|| (0, key.clone()));
+            templates[index].push((root.join(&content.source), Some(name.clone())));
+            names.insert(content.source.clone(), (index, name));
+        }
+
+        let mut teras: Vec<Tera> = Vec::new();
+        for files in templates {
+            let mut tera = Tera::default();
+            // generated files are not html, never escape variables
+            tera.autoescape_on(vec![]);
+            tera.add_template_files(files)?;
+            teras.push(tera);
+        }
+
+        Ok(Self { root, teras, names })
+    }
+
+    /// Renders variables into a destination path. Paths without any template
+    /// tags are returned as they are.
+    pub fn render_path(&self, path: &Path, variables: &Context) -> Result<PathBuf, SkelError> {
+        let path_str = path.to_string_lossy();
+        if !path_str.contains("{{") && !path_str.contains("{%") {
+            return Ok(path.to_path_buf());
+        }
+
+        let rendered = Tera::one_off(&path_str, variables, false)?;
+        Ok(PathBuf::from(rendered))
+    }
+
+    /// Renders a destination path and makes sure it stays inside the project:
+    /// it has to be relative and can't go up with `..`.
+    pub fn render_destination(&self, path: &Path, variables: &Context) -> Result<PathBuf, SkelError> {
+        let destination = self.render_path(path, variables)?;
+        let is_inside = destination.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !is_inside || destination.file_name().is_none() {
+            return Err(SkelError::InvalidDestination(destination));
+        }
+
+        Ok(destination)
+    }
+
+    /// Evaluates a `when` condition. A bare expression like `use_docker and
+    /// not ci`, or a single `{{ }}` tag around one, is used as the
+    /// expression of an if tag, so undefined variables are false in both.
+    /// Other templates are rendered and the output checked.
+    pub fn evaluate_condition(&self, condition: &str, variables: &Context) -> Result<bool, SkelError> {
+        let expression = condition
+            .trim()
+            .strip_prefix("{{")
+            .and_then(|rest| rest.strip_suffix("}}"))
+            .map(|inner| inner.trim_matches('-'))
+            .filter(|inner| !inner.contains("{{") && !inner.contains("}}") && !inner.contains("{%"))
+            .unwrap_or(condition);
+
+        let template = if expression.contains("{{") || expression.contains("{%") {
+            expression.to_owned()
+        } else {
+            format!("{{% if {} %}}true{{% endif %}}", expression)
+        };
+
+        let rendered = Tera::one_off(&template, variables, false)?;
+        Ok(!matches!(rendered.trim(), "" | "false" | "0"))
+    }
+
+    /// The permissions to write a piece of content with: the mode it was
+    /// given, or else the permissions of its source. Symlinks have none.
+    pub fn mode(&self, content: &Content) -> Result<Option<u32>, SkelError> {
+        match (&content.kind, content.mode) {
+            (ContentKind::Symlink, _) => Ok(None),
+            (_, Some(mode)) => Ok(Some(mode)),
+            (_, None) => Ok(file_mode(&self.root.join(&content.source))?),
+        }
+    }
+
+    /// Renders the contents of a piece of content. For symlinks that is the
+    /// link target: the `target` it was given, or else its source's contents.
+    pub fn render(&self, content: &Content, variables: &Context) -> Result<Vec<u8>, SkelError> {
+        match content.kind {
+            ContentKind::File => Ok(fs::read(self.root.join(&content.source))?),
+            ContentKind::Symlink => {
+                let target = match &content.target {
+                    Some(target) => self.render_path(Path::new(target), variables)?,
+                    None => PathBuf::from(fs::read_to_string(self.root.join(&content.source))?.trim_end()),
+                };
+                Ok(target.to_string_lossy().into_owned().into_bytes())
+            }
+            ContentKind::Template => {
+                let (index, name) = match self.names.get(&content.source) {
+                    Some((index, name)) => (*index, name.clone()),
+                    None => (0, content.source.to_string_lossy().into_owned()),
+                };
+                let rendered = self.teras[index].render(&name, variables)?;
+                Ok(rendered.into_bytes())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn skeleton_with_content(files: &[(&str, &str, ContentKind)]) -> (TempDir, Skeleton) {
+        let dir = TempDir::new().unwrap();
+        let mut skeleton = Skeleton::new();
+        skeleton.skeleton = dir.path().to_path_buf();
+
+        for (path, data, kind) in files {
+            let source = skeleton.content_root().join(path);
+            fs::create_dir_all(source.parent().unwrap()).unwrap();
+            fs::write(source, data).unwrap();
+
+            let mut content = Content::from_source(&PathBuf::from(path), None);
+            content.kind = kind.clone();
+            skeleton.content.insert(path.to_string(), content);
+        }
+
+        (dir, skeleton)
+    }
+
+    #[test]
+    fn copies_files_verbatim() {
+        let (_dir, skeleton) = skeleton_with_content(&[("one", "{{ name }}", ContentKind::File)]);
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let content = skeleton.content.get("one").unwrap();
+        assert_eq!(renderer.render(content, &skeleton.variables).unwrap(), b"{{ name }}");
+    }
+
+    #[test]
+    fn renders_templates_with_variables() {
+        let (_dir, mut skeleton) = skeleton_with_content(&[
+            ("one.html", "<{{ name }}>", ContentKind::Template),
+            ("two", "{% include \"one.html\" %}!", ContentKind::Template),
+        ]);
+        skeleton.variables.insert("name", "a & b");
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let one = skeleton.content.get("one.html").unwrap();
+        assert_eq!(renderer.render(one, &skeleton.variables).unwrap(), b"<a & b>");
+
+        let two = skeleton.content.get("two").unwrap();
+        assert_eq!(renderer.render(two, &skeleton.variables).unwrap(), b"<a & b>!");
+    }
+
+    #[test]
+    fn renders_symlink_targets() {
+        let (_dir, mut skeleton) = skeleton_with_content(&[
+            ("one", "../one\n", ContentKind::Symlink),
+            ("two", "", ContentKind::Symlink),
+        ]);
+        skeleton.content.get_mut("two").unwrap().target = Some("../{{ name }}".to_owned());
+        skeleton.variables.insert("name", "two");
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let one = skeleton.content.get("one").unwrap();
+        assert_eq!(renderer.render(one, &skeleton.variables).unwrap(), b"../one");
+
+        let two = skeleton.content.get("two").unwrap();
+        assert_eq!(renderer.render(two, &skeleton.variables).unwrap(), b"../two");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn keeps_source_modes_unless_given_one() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_dir, mut skeleton) = skeleton_with_content(&[
+            ("one.sh", "", ContentKind::File),
+            ("two.sh", "", ContentKind::Template),
+        ]);
+        let one = skeleton.content_root().join("one.sh");
+        fs::set_permissions(&one, fs::Permissions::from_mode(0o750)).unwrap();
+        skeleton.content.get_mut("two.sh").unwrap().mode = Some(0o755);
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        assert_eq!(renderer.mode(skeleton.content.get("one.sh").unwrap()).unwrap(), Some(0o750));
+        assert_eq!(renderer.mode(skeleton.content.get("two.sh").unwrap()).unwrap(), Some(0o755));
+    }
+
+    #[test]
+    fn includes_templates_by_their_path_in_the_content_tree() {
+        let project = TempDir::new().unwrap();
+        let skeleton_root = project.path().join(".skeleton");
+        fs::create_dir_all(skeleton_root.join("content")).unwrap();
+        fs::write(skeleton_root.join("content/partial.txt"), "{{ name }}").unwrap();
+        fs::write(skeleton_root.join("content/main.txt"), "{% include \"partial.txt\" %}!").unwrap();
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "partial.txt" {
+                kind "template"
+            }
+            content "main.txt" {
+                kind "template"
+            }
+        "#).unwrap();
+        fs::write(project.path().join(".skeleton.kdl"), r#"
+            variables {
+                name "skel"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let main = skeleton.content.get("main.txt").unwrap();
+        assert_eq!(renderer.render(main, &skeleton.variables).unwrap(), b"skel!");
+    }
+
+    #[test]
+    fn includes_templates_inside_mounted_skeletons() {
+        let project = TempDir::new().unwrap();
+        let skeleton_root = project.path().join("web");
+        fs::create_dir_all(skeleton_root.join("content")).unwrap();
+        fs::write(skeleton_root.join("content/partial.txt"), "{{ name }}").unwrap();
+        fs::write(skeleton_root.join("content/main.txt"), "{% include \"partial.txt\" %}!").unwrap();
+        fs::write(skeleton_root.join("skeleton.kdl"), r#"
+            content "partial.txt" {
+                kind "template"
+            }
+            content "main.txt" {
+                kind "template"
+            }
+        "#).unwrap();
+        fs::write(project.path().join(".skeleton.kdl"), r#"
+            skeleton "web" {
+                mount "frontend"
+            }
+            variables {
+                name "skel"
+            }
+        "#).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let main = skeleton.content.get("frontend/main.txt").unwrap();
+        assert_eq!(renderer.render(main, &skeleton.variables).unwrap(), b"skel!");
+    }
+
+    #[test]
+    fn renders_destination_paths() {
+        let (_dir, mut skeleton) = skeleton_with_content(&[]);
+        skeleton.variables.insert("crate_name", "skel");
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let path = renderer.render_path(Path::new("src/{{ crate_name }}/lib.rs"), &skeleton.variables).unwrap();
+        assert_eq!(path, PathBuf::from("src/skel/lib.rs"));
+
+        let path = renderer.render_path(Path::new("src/{# not a template #}.rs"), &skeleton.variables).unwrap();
+        assert_eq!(path, PathBuf::from("src/{# not a template #}.rs"));
+    }
+
+    #[test]
+    fn rejects_destinations_outside_the_project() {
+        let (_dir, mut skeleton) = skeleton_with_content(&[]);
+        skeleton.variables.insert("name", "../../etc/x");
+        skeleton.variables.insert("root", "/etc/x");
+        skeleton.variables.insert("crate_name", "skel");
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let render = |path: &str| renderer.render_destination(Path::new(path), &skeleton.variables);
+        assert_eq!(render("./src/{{ crate_name }}").unwrap(), PathBuf::from("./src/skel"));
+        assert!(matches!(render("src/{{ name }}").unwrap_err(), SkelError::InvalidDestination(_)));
+        assert!(matches!(render("{{ root }}").unwrap_err(), SkelError::InvalidDestination(_)));
+        assert!(matches!(render("{{ missing | default(value=\"\") }}").unwrap_err(), SkelError::InvalidDestination(_)));
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let (_dir, mut skeleton) = skeleton_with_content(&[]);
+        skeleton.variables.insert("use_docker", &true);
+        skeleton.variables.insert("use_ci", &false);
+        skeleton.variables.insert("ci", "none");
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let evaluate = |condition| renderer.evaluate_condition(condition, &skeleton.variables).unwrap();
+        assert!(evaluate("{{ use_docker }}"));
+        assert!(!evaluate("{{ use_ci }}"));
+        assert!(evaluate("use_docker"));
+        assert!(!evaluate("use_docker and ci != \"none\""));
+    }
+
+    #[test]
+    fn treats_undefined_variables_as_false_in_expressions() {
+        let (_dir, skeleton) = skeleton_with_content(&[]);
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let evaluate = |condition| renderer.evaluate_condition(condition, &skeleton.variables).unwrap();
+        assert!(!evaluate("missing"));
+        assert!(evaluate("not missing"));
+    }
+
+    #[test]
+    fn treats_undefined_variables_as_false_in_template_tags() {
+        let (_dir, skeleton) = skeleton_with_content(&[]);
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let evaluate = |condition| renderer.evaluate_condition(condition, &skeleton.variables).unwrap();
+        assert!(!evaluate("{{ missing }}"));
+        assert!(evaluate("{{- not missing -}}"));
+    }
+
+    #[test]
+    fn surfaces_template_errors() {
+        let (_dir, skeleton) = skeleton_with_content(&[("one", "{{ missing }}", ContentKind::Template)]);
+        let renderer = Renderer::new(&skeleton).unwrap();
+
+        let content = skeleton.content.get("one").unwrap();
+        let result = renderer.render(content, &skeleton.variables);
+        assert!(matches!(result.unwrap_err(), SkelError::TemplateError(_)));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::process::Command;
+
+use crate::config::task::TaskStep;
+use crate::error::SkelError;
+use crate::skeleton::Skeleton;
+
+/// Runs the named task from the project root. Any `args` are appended to
+/// every `exec` step of the task.
+pub fn run(skeleton: &Skeleton, name: &str, args: &[String]) -> Result<(), SkelError> {
+    let mut stack: Vec<String> = Vec::new();
+    run_task(skeleton, name, args, HashMap::new(), &mut stack)
+}
+
+fn run_task(
+    skeleton: &Skeleton,
+    name: &str,
+    args: &[String],
+    mut env: HashMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<(), SkelError> {
+    let task = match skeleton.tasks.get(name) {
+        Some(task) => task,
+        None => return Err(SkelError::UnknownTask(name.to_owned())),
+    };
+
+    stack.push(name.to_owned());
+    if stack[..stack.len() - 1].contains(&task.name) {
+        return Err(SkelError::TaskLoop(stack.clone()));
+    }
+
+    for step in &task.steps {
+        match step {
+            TaskStep::Env(vars) => {
+                env.extend(vars.clone());
+            }
+            TaskStep::Exec(command, command_args) => {
+                let status = Command::new(command)
+                    .args(command_args)
+                    .args(args)
+                    .envs(&env)
+                    .current_dir(&skeleton.project)
+                    .status()?;
+
+                if !status.success() {
+                    return Err(SkelError::TaskFailed(task.name.clone(), status));
+                }
+            }
+            TaskStep::Task(subtask, subtask_args) => {
+                // subtasks inherit the environment but can't change ours
+                run_task(skeleton, subtask, subtask_args, env.clone(), stack)?;
+            }
+        };
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn project_with_tasks(config: &str) -> (TempDir, Skeleton) {
+        let project = TempDir::new().unwrap();
+        fs::write(project.path().join(".skeleton.kdl"), config).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        (project, skeleton)
+    }
+
+    #[test]
+    fn runs_exec_steps_in_the_project_root() {
+        let (project, skeleton) = project_with_tasks(r#"
+            task "test" {
+                exec "touch" "one"
+            }
+        "#);
+
+        run(&skeleton, "test", &["two".to_owned()]).unwrap();
+        assert!(project.path().join("one").is_file());
+        assert!(project.path().join("two").is_file());
+    }
+
+    #[test]
+    fn passes_env_to_exec_and_subtasks() {
+        let (project, skeleton) = project_with_tasks(r#"
+            task "test" {
+                env first="one"
+                task "subtask" "out.txt"
+            }
+            task "subtask" {
+                env second="two"
+                exec "sh" "-c" "printf '%s %s' \"$first\" \"$second\" > \"$0\""
+            }
+        "#);
+
+        run(&skeleton, "test", &[]).unwrap();
+        assert_eq!(fs::read_to_string(project.path().join("out.txt")).unwrap(), "one two");
+    }
+
+    #[test]
+    fn errors_for_unknown_tasks() {
+        let (_project, skeleton) = project_with_tasks("");
+
+        let result = run(&skeleton, "missing", &[]);
+        assert!(matches!(result.unwrap_err(), SkelError::UnknownTask(name) if name == "missing"));
+    }
+
+    #[test]
+    fn errors_for_failed_commands() {
+        let (_project, skeleton) = project_with_tasks(r#"
+            task "test" {
+                exec "false"
+            }
+        "#);
+
+        let result = run(&skeleton, "test", &[]);
+        assert!(matches!(result.unwrap_err(), SkelError::TaskFailed(name, _) if name == "test"));
+    }
+
+    #[test]
+    fn errors_for_task_loops() {
+        let (_project, skeleton) = project_with_tasks(r#"
+            task "one" {
+                task "two"
+            }
+            task "two" {
+                task "one"
+            }
+        "#);
+
+        let result = run(&skeleton, "one", &[]);
+        assert!(matches!(
+            result.unwrap_err(),
+            SkelError::TaskLoop(stack) if stack == vec!["one", "two", "one"]
+        ));
+    }
+}
@@ -1,21 +1,46 @@
 use std::collections::HashMap;
 use std::default::Default;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tera::Context;
 
-use crate::config::{ProjectConfig, SkeletonConfig, Task};
+use crate::config::{self, Lockfile, ProjectConfig, SkeletonConfig, Task};
 use crate::content::Content;
 use crate::error::SkelError;
+use crate::source::{self, ResolvedSource};
 
 #[derive(Debug, Default)]
 pub struct Skeleton {
     pub project: PathBuf,
+    /// The first skeleton applied to the project
     pub skeleton: PathBuf,
+    /// Every skeleton applied to the project, in order
+    pub sources: Vec<ResolvedSource>,
+    pub features: Vec<String>,
+    /// Where apply records what it wrote, next to the project config
+    pub lockfile: PathBuf,
     pub content: HashMap<String, Content>,
+    /// For each content key, the index in `sources` of the skeleton it came
+    /// from and its key in that skeleton before it was mounted
+    pub origins: HashMap<String, (usize, String)>,
     pub variables: Context,
     pub tasks: HashMap<String, Task>,
 }
 
+/// Moves content from a skeleton under its mount point in the project.
+/// Sources become absolute so content from different skeletons can be
+/// read from the same place.
+fn mount_content(root: &Path, mount: &Path, key: String, mut content: Content) -> (String, Content) {
+    content.source = root.join(&content.source);
+    content.destination = mount.join(&content.destination);
+    content.dependencies = content
+        .dependencies
+        .iter()
+        .map(|dependency| mount.join(dependency).to_string_lossy().into_owned())
+        .collect();
+
+    (mount.join(key).to_string_lossy().into_owned(), content)
+}
+
 impl Skeleton {
     pub fn new() -> Self {
         Default::default()
@@ -23,28 +48,120 @@ impl Skeleton {
 
     pub fn from_config_file(config_file: PathBuf) -> Result<Self, SkelError> {
         let project_config = ProjectConfig::read_from(&config_file)?;
-        let skeleton_config = SkeletonConfig::read_from(&project_config.skeleton.join("skeleton.kdl"))?;
 
-        let mut variables = Context::new();
-        variables.extend(skeleton_config.variables);
-        variables.extend(project_config.variables);
+        let cache_dir = source::cache_dir();
+        let sources = project_config
+            .skeletons
+            .iter()
+            .map(|skeleton_source| source::resolve(skeleton_source, &cache_dir))
+            .collect::<Result<Vec<ResolvedSource>, SkelError>>()?;
 
-        let mut tasks: HashMap<String, Task> = HashMap::new();
-        for (key, value) in skeleton_config.tasks {
-            tasks.insert(key, value);
+        let mut skeleton = Self::from_sources(project_config.root, sources, project_config.features)?;
+        skeleton.variables.extend(project_config.variables);
+        skeleton.tasks.extend(project_config.tasks);
+        skeleton.lockfile = config_file.with_extension("lock");
+
+        Ok(skeleton)
+    }
+
+    /// Rebuilds the skeleton as it was when the lockfile was written: the
+    /// same skeleton versions, features and final variables.
+    pub fn from_lockfile(project: PathBuf, lockfile: &Lockfile) -> Result<Self, SkelError> {
+        let cache_dir = source::cache_dir();
+        let sources = lockfile
+            .skeletons
+            .iter()
+            .map(|locked| source::resolve_locked(&locked.source, locked.version.as_deref(), &cache_dir))
+            .collect::<Result<Vec<ResolvedSource>, SkelError>>()?;
+
+        let mut skeleton = Self::from_sources(project, sources, lockfile.features.clone())?;
+        skeleton.variables = lockfile.variables.clone();
+
+        Ok(skeleton)
+    }
+
+    fn from_sources(project: PathBuf, sources: Vec<ResolvedSource>, features: Vec<String>) -> Result<Self, SkelError> {
+        let mut skeleton_configs: Vec<SkeletonConfig> = Vec::new();
+        for resolved in &sources {
+            skeleton_configs.push(SkeletonConfig::read_from(&resolved.root.join("skeleton.kdl"))?);
         }
-        for (key, value) in project_config.tasks {
-            tasks.insert(key, value);
+
+        for name in &features {
+            if !skeleton_configs.iter().any(|config| config.features.contains_key(name)) {
+                return Err(SkelError::UnknownFeature(name.to_owned()));
+            }
+        }
+
+        let mut content: HashMap<String, Content> = HashMap::new();
+        let mut origins: HashMap<String, (usize, String)> = HashMap::new();
+        // the destinations of every skeleton so far, and which source writes each
+        let mut destinations: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut variables = Context::new();
+        let mut tasks: HashMap<String, Task> = HashMap::new();
+
+        for (index, (resolved, mut skeleton_config)) in sources.iter().zip(skeleton_configs).enumerate() {
+            let mut skeleton_content = skeleton_config.content;
+            variables.extend(skeleton_config.variables);
+            tasks.extend(skeleton_config.tasks);
+
+            // enabled features are layered between the skeleton and the project,
+            // but can't replace content the skeleton or another feature provides
+            let mut owners: HashMap<String, String> = HashMap::new();
+            for name in &features {
+                if let Some(feature) = skeleton_config.features.remove(name) {
+                    for (key, value) in feature.content {
+                        if skeleton_content.contains_key(&key) {
+                            let owner = owners.get(&key).map(|owner| format!("feature {}", owner));
+                            let owner = owner.unwrap_or_else(|| "the skeleton".to_owned());
+                            return Err(SkelError::FeatureConflict(key, owner, name.to_owned()));
+                        }
+                        owners.insert(key.clone(), name.to_owned());
+                        skeleton_content.insert(key, value);
+                    }
+                    variables.extend(feature.variables);
+                    tasks.extend(feature.tasks);
+                }
+            }
+
+            let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+            for (own_key, value) in skeleton_content {
+                let (key, value) = mount_content(&skeleton_config.root, &resolved.source.mount, own_key.clone(), value);
+                // conflicts within one skeleton are left for the plan, which
+                // sees rendered destinations
+                if let Some(existing) = destinations.get(&value.destination) {
+                    return Err(SkelError::DestinationConflict(value.destination, existing.clone(), value.source));
+                }
+                claimed.insert(value.destination.clone(), value.source.clone());
+                if let Some(existing) = content.get(&key) {
+                    return Err(SkelError::DuplicateContent(key, existing.source.clone(), value.source));
+                }
+
+                origins.insert(key.clone(), (index, own_key));
+                content.insert(key, value);
+            }
+            destinations.extend(claimed);
         }
 
         Ok(Self {
-            project: project_config.root,
-            skeleton: project_config.skeleton,
-            content: skeleton_config.content,
+            project,
+            skeleton: sources.first().map(|resolved| resolved.root.clone()).unwrap_or_default(),
+            sources,
+            features,
+            content,
+            origins,
             variables,
             tasks,
+            ..Default::default()
         })
     }
+
+    pub fn content_root(&self) -> PathBuf {
+        self.skeleton.join("content")
+    }
+
+    pub fn calculate(&self) -> Vec<Content> {
+        config::skeleton::calculate(&self.content)
+    }
 }
 
 #[cfg(test)]
@@ -78,4 +195,186 @@ mod tests {
         assert_eq!(skeleton.variables, Context::new());
         assert_eq!(skeleton.tasks, HashMap::new());
     }
+
+    mod mounts {
+        use super::*;
+        use std::fs;
+
+        fn write_skeleton(root: &Path, files: &[&str]) {
+            fs::create_dir_all(root.join("content")).unwrap();
+            for file in files {
+                fs::write(root.join("content").join(file), "").unwrap();
+            }
+        }
+
+        #[test]
+        fn mounts_skeletons_under_subdirectories() {
+            let project = TempDir::new().unwrap();
+            write_skeleton(&project.path().join("web"), &["package.json"]);
+            write_skeleton(&project.path().join("rust"), &["Cargo.toml", "README.md"]);
+            fs::write(project.path().join("rust/skeleton.kdl"), r#"
+                content "Cargo.toml" {
+                    depends_on "README.md"
+                }
+            "#).unwrap();
+            fs::write(project.path().join(".skeleton.kdl"), r#"
+                skeleton "web" {
+                    mount "frontend"
+                }
+                skeleton "rust" {
+                    mount "backend"
+                }
+            "#).unwrap();
+
+            let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+            assert_eq!(skeleton.skeleton, project.path().join("web"));
+
+            let package = skeleton.content.get("frontend/package.json").unwrap();
+            assert_eq!(package.source, project.path().join("web/content/package.json"));
+            assert_eq!(package.destination, PathBuf::from("frontend/package.json"));
+
+            let cargo = skeleton.content.get("backend/Cargo.toml").unwrap();
+            assert_eq!(cargo.source, project.path().join("rust/content/Cargo.toml"));
+            assert_eq!(cargo.destination, PathBuf::from("backend/Cargo.toml"));
+            assert_eq!(cargo.dependencies, vec!["backend/README.md"]);
+
+            let order: Vec<PathBuf> = skeleton.calculate().into_iter().map(|c| c.destination).collect();
+            assert_eq!(order, vec![
+                PathBuf::from("backend/README.md"),
+                PathBuf::from("frontend/package.json"),
+                PathBuf::from("backend/Cargo.toml"),
+            ]);
+        }
+
+        #[test]
+        fn errors_when_skeletons_conflict() {
+            let project = TempDir::new().unwrap();
+            write_skeleton(&project.path().join("one"), &["README.md"]);
+            write_skeleton(&project.path().join("two"), &["README.md"]);
+            fs::write(project.path().join(".skeleton.kdl"), r#"
+                skeleton "one"
+                skeleton "two"
+            "#).unwrap();
+
+            let result = Skeleton::from_config_file(project.path().join(".skeleton.kdl"));
+            assert!(matches!(
+                result.unwrap_err(),
+                SkelError::DestinationConflict(destination, _, _) if destination == Path::new("README.md")
+            ));
+        }
+
+        #[test]
+        fn errors_when_skeletons_write_the_same_destination() {
+            let project = TempDir::new().unwrap();
+            write_skeleton(&project.path().join("one"), &["README.md"]);
+            write_skeleton(&project.path().join("two"), &["README.txt"]);
+            fs::write(project.path().join("two/skeleton.kdl"), r#"
+                content "README.txt" {
+                    destination "README.md"
+                }
+            "#).unwrap();
+            fs::write(project.path().join(".skeleton.kdl"), r#"
+                skeleton "one"
+                skeleton "two"
+            "#).unwrap();
+
+            let result = Skeleton::from_config_file(project.path().join(".skeleton.kdl"));
+            assert!(matches!(
+                result.unwrap_err(),
+                SkelError::DestinationConflict(destination, _, _) if destination == Path::new("README.md")
+            ));
+        }
+    }
+
+    mod features {
+        use super::*;
+        use std::fs;
+
+        fn project_with_features(enabled: &str) -> TempDir {
+            let project = TempDir::new().unwrap();
+            let skeleton_root = project.path().join(".skeleton");
+            fs::create_dir_all(skeleton_root.join("content")).unwrap();
+            fs::write(skeleton_root.join("content/README.md"), "").unwrap();
+            fs::write(skeleton_root.join("content/Dockerfile"), "").unwrap();
+            fs::write(skeleton_root.join("skeleton.kdl"), r#"
+                variables {
+                    image "scratch"
+                }
+                feature "docker" {
+                    content "Dockerfile"
+                    variables {
+                        image "alpine"
+                        registry "ghcr.io"
+                    }
+                    task "build" {
+                        exec "docker" "build" "."
+                    }
+                }
+            "#).unwrap();
+            fs::write(project.path().join(".skeleton.kdl"), format!(r#"
+                {}
+                variables {{
+                    registry "example.com"
+                }}
+            "#, enabled)).unwrap();
+
+            project
+        }
+
+        #[test]
+        fn excludes_disabled_features() {
+            let project = project_with_features("");
+            let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+
+            let mut keys: Vec<&String> = skeleton.content.keys().collect();
+            keys.sort();
+            assert_eq!(keys, vec!["README.md"]);
+            assert_eq!(skeleton.variables.get("image").unwrap(), "scratch");
+            assert!(!skeleton.tasks.contains_key("build"));
+        }
+
+        #[test]
+        fn merges_enabled_features() {
+            let project = project_with_features(r#"features "docker""#);
+            let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+
+            let mut keys: Vec<&String> = skeleton.content.keys().collect();
+            keys.sort();
+            assert_eq!(keys, vec!["Dockerfile", "README.md"]);
+            assert_eq!(skeleton.variables.get("image").unwrap(), "alpine");
+            // project variables still win over features
+            assert_eq!(skeleton.variables.get("registry").unwrap(), "example.com");
+            assert!(skeleton.tasks.contains_key("build"));
+        }
+
+        #[test]
+        fn errors_when_features_provide_the_same_content() {
+            let project = project_with_features(r#"features "docker" "ci""#);
+            let skeleton_root = project.path().join(".skeleton");
+            let base_root = project.path().join("base");
+            fs::create_dir_all(base_root.join("content")).unwrap();
+            fs::write(base_root.join("content/Dockerfile"), "").unwrap();
+            fs::rename(skeleton_root.join("skeleton.kdl"), base_root.join("skeleton.kdl")).unwrap();
+            fs::write(skeleton_root.join("skeleton.kdl"), r#"
+                extends "../base"
+                feature "ci" {
+                    content "Dockerfile"
+                }
+            "#).unwrap();
+
+            let result = Skeleton::from_config_file(project.path().join(".skeleton.kdl"));
+            assert!(matches!(
+                result.unwrap_err(),
+                SkelError::FeatureConflict(key, first, second)
+                    if key == "Dockerfile" && first == "feature docker" && second == "ci"
+            ));
+        }
+
+        #[test]
+        fn errors_for_unknown_features() {
+            let project = project_with_features(r#"features "dokcer""#);
+            let result = Skeleton::from_config_file(project.path().join(".skeleton.kdl"));
+            assert!(matches!(result.unwrap_err(), SkelError::UnknownFeature(name) if name == "dokcer"));
+        }
+    }
 }
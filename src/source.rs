@@ -0,0 +1,502 @@
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use zip::ZipArchive;
+
+use crate::config::SkeletonSource;
+use crate::error::SkelError;
+
+/// Where fetched skeletons are kept. `SKEL_CACHE_DIR` takes precedence over
+/// the usual user cache directory.
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("SKEL_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return PathBuf::from(dir).join("skel");
+    }
+
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".cache/skel"),
+        None => env::temp_dir().join("skel"),
+    }
+}
+
+/// A skeleton source once it can be read from the local directory `root`.
+/// `version` pins what was fetched: the commit a git ref pointed at, or the
+/// hash of an archive. Local skeletons have no version.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResolvedSource {
+    pub source: SkeletonSource,
+    pub root: PathBuf,
+    pub version: Option<String>,
+}
+
+/// Finds the local directory a skeleton can be read from, fetching it into
+/// `cache_dir` first when it lives somewhere else.
+pub fn resolve(source: &SkeletonSource, cache_dir: &Path) -> Result<ResolvedSource, SkelError> {
+    let (root, version) = match (&source.repository, &source.archive) {
+        (Some(repository), _) => {
+            let reference = source.reference.as_deref().unwrap_or("HEAD");
+            resolve_git(repository, reference, cache_dir)?
+        }
+        (None, Some(archive)) => resolve_archive(archive, cache_dir)?,
+        (None, None) => (source.path.clone(), None),
+    };
+
+    Ok(ResolvedSource {
+        source: source.clone(),
+        root,
+        version,
+    })
+}
+
+/// Resolves a source at the version a lockfile recorded for it, rather than
+/// whatever its ref or archive points to now. Archives are only kept by
+/// hash, so an old version has to still be in the cache.
+pub fn resolve_locked(source: &SkeletonSource, version: Option<&str>, cache_dir: &Path) -> Result<ResolvedSource, SkelError> {
+    match (&source.repository, &source.archive, version) {
+        (Some(repository), _, Some(version)) => {
+            let (root, version) = resolve_git(repository, version, cache_dir)?;
+            Ok(ResolvedSource {
+                source: source.clone(),
+                root,
+                version,
+            })
+        }
+        (None, Some(archive), Some(version)) => {
+            let extracted = cache_dir.join("archive").join(version);
+            if extracted.is_dir() {
+                return Ok(ResolvedSource {
+                    source: source.clone(),
+                    root: skeleton_root(extracted),
+                    version: Some(version.to_owned()),
+                });
+            }
+
+            // the archive itself may still be the version that was locked
+            let resolved = resolve(source, cache_dir)?;
+            if resolved.version.as_deref() != Some(version) {
+                return Err(SkelError::ArchiveError(
+                    archive.to_owned(),
+                    format!("version {} is no longer in the cache", version),
+                ));
+            }
+
+            Ok(resolved)
+        }
+        _ => resolve(source, cache_dir),
+    }
+}
+
+/// Extracts an archive into a directory named after the hash of its
+/// contents, so a new release lands somewhere new and an unchanged one is
+/// only ever extracted once.
+fn resolve_archive(archive: &Path, cache_dir: &Path) -> Result<(PathBuf, Option<String>), SkelError> {
+    let data = fs::read(archive)?;
+    let version = hash(&data);
+    let archive_cache = cache_dir.join("archive");
+    let extracted = archive_cache.join(&version);
+
+    if !extracted.is_dir() {
+        // extract next to the final directory and move it into place, so an
+        // interrupted extraction is never mistaken for a complete one
+        let partial = archive_cache.join(format!("{}.partial", version));
+        if partial.exists() {
+            fs::remove_dir_all(&partial)?;
+        }
+
+        let result = extract(archive, &data, &partial).and_then(|_| Ok(fs::rename(&partial, &extracted)?));
+        if let Err(err) = result {
+            let _ = fs::remove_dir_all(&partial);
+            return Err(err);
+        }
+    }
+
+    Ok((skeleton_root(extracted), Some(version)))
+}
+
+fn extract(archive: &Path, data: &[u8], destination: &Path) -> Result<(), SkelError> {
+    fs::create_dir_all(destination)?;
+
+    let archive_error = |err: String| SkelError::ArchiveError(archive.to_owned(), err);
+    if archive.extension().is_some_and(|ext| ext == "zip") {
+        ZipArchive::new(Cursor::new(data))
+            .and_then(|mut zip| zip.extract(destination))
+            .map_err(|err| archive_error(err.to_string()))
+    } else {
+        tar::Archive::new(GzDecoder::new(data))
+            .unpack(destination)
+            .map_err(|err| archive_error(err.to_string()))
+    }
+}
+
+/// Release archives usually wrap everything in a single top level
+/// directory, in which case the skeleton lives inside it.
+fn skeleton_root(extracted: PathBuf) -> PathBuf {
+    if extracted.join("skeleton.kdl").exists() || extracted.join("content").exists() {
+        return extracted;
+    }
+
+    let entries: Vec<PathBuf> = match fs::read_dir(&extracted) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+        Err(_) => return extracted,
+    };
+
+    match entries.as_slice() {
+        [only] if only.is_dir() => only.to_owned(),
+        _ => extracted,
+    }
+}
+
+/// Keeps one bare clone per repository, and checks out every commit that
+/// was asked for into its own directory next to it. Branches and tags can
+/// move, so the clone is fetched before resolving them; a full commit id
+/// only needs a fetch when the clone doesn't have it yet. Commits never
+/// change, so a checkout is reused as long as it exists.
+fn resolve_git(repository: &str, reference: &str, cache_dir: &Path) -> Result<(PathBuf, Option<String>), SkelError> {
+    // anything starting with a dash would be read as an option by git
+    if reference.starts_with('-') {
+        return Err(SkelError::GitError(format!("invalid ref {} for {}", reference, repository)));
+    }
+
+    let repo_cache = cache_dir.join("git").join(hash(repository.as_bytes()));
+    let bare = repo_cache.join("repo");
+
+    let mut fetched = false;
+    if !bare.is_dir() {
+        fs::create_dir_all(&repo_cache)?;
+        git(&repo_cache, &["clone", "--bare", "--quiet", "--", repository, "repo"])?;
+        fetched = true;
+    }
+
+    let commit_ref = format!("{}^{{commit}}", reference);
+    let rev_parse = || git(&bare, &["rev-parse", "--verify", "--quiet", "--end-of-options", &commit_ref]);
+    let commit = match rev_parse() {
+        Ok(commit) if fetched || is_commit_id(reference) => commit,
+        _ => {
+            git(&bare, &[
+                "fetch",
+                "--quiet",
+                "--force",
+                "origin",
+                "+refs/heads/*:refs/heads/*",
+                "+refs/tags/*:refs/tags/*",
+            ])?;
+            rev_parse().map_err(|_| SkelError::GitError(format!("unknown ref {} in {}", reference, repository)))?
+        }
+    };
+
+    let checkout = repo_cache.join(&commit);
+    if !checkout.is_dir() {
+        // forget checkouts that were deleted, or adding this one again fails
+        git(&bare, &["worktree", "prune"])?;
+        let checkout_str = checkout.to_string_lossy();
+        git(&bare, &["worktree", "add", "--quiet", "--detach", "--", &checkout_str, &commit])?;
+    }
+
+    Ok((checkout, Some(commit)))
+}
+
+/// Whether a ref is a full commit id, which can never point anywhere else.
+fn is_commit_id(reference: &str) -> bool {
+    matches!(reference.len(), 40 | 64) && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, SkelError> {
+    let output = Command::new("git").args(args).current_dir(dir).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(SkelError::GitError(format!("git {}: {}", args[0], stderr.trim())));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+pub fn hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn run_git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=skel", "-c", "user.email=skel@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// A bare repository with a v1 tag and a newer commit on main.
+    fn bare_repository() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let work = dir.path().join("work");
+        fs::create_dir_all(work.join("content")).unwrap();
+        run_git(&work, &["init", "--quiet", "--initial-branch=main"]);
+
+        fs::write(work.join("content/version"), "1").unwrap();
+        run_git(&work, &["add", "."]);
+        run_git(&work, &["commit", "--quiet", "-m", "one"]);
+        run_git(&work, &["tag", "v1"]);
+
+        fs::write(work.join("content/version"), "2").unwrap();
+        run_git(&work, &["commit", "--quiet", "-am", "two"]);
+
+        run_git(dir.path(), &["clone", "--quiet", "--bare", "work", "skeleton.git"]);
+        let bare = dir.path().join("skeleton.git");
+        (dir, bare)
+    }
+
+    #[test]
+    fn returns_local_paths_as_they_are() {
+        let cache = TempDir::new().unwrap();
+        let source = SkeletonSource {
+            path: PathBuf::from("/skeleton"),
+            ..Default::default()
+        };
+
+        let resolved = resolve(&source, cache.path()).unwrap();
+        assert_eq!(resolved.root, PathBuf::from("/skeleton"));
+        assert_eq!(resolved.version, None);
+    }
+
+    #[test]
+    fn checks_out_git_refs() {
+        let (_dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let mut source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("v1".to_owned()),
+            ..Default::default()
+        };
+        let v1 = resolve(&source, cache.path()).unwrap();
+        assert_eq!(fs::read_to_string(v1.root.join("content/version")).unwrap(), "1");
+        assert_eq!(v1.version.as_ref().unwrap().len(), 40);
+
+        source.reference = None;
+        let head = resolve(&source, cache.path()).unwrap();
+        assert_eq!(fs::read_to_string(head.root.join("content/version")).unwrap(), "2");
+        assert_ne!(head.version, v1.version);
+
+        // resolving again reuses the checkout
+        source.reference = Some("v1".to_owned());
+        assert_eq!(resolve(&source, cache.path()).unwrap(), v1);
+    }
+
+    #[test]
+    fn fetches_refs_missing_from_the_cache() {
+        let (dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let mut source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("v1".to_owned()),
+            ..Default::default()
+        };
+        resolve(&source, cache.path()).unwrap();
+
+        let work = dir.path().join("work");
+        fs::write(work.join("content/version"), "3").unwrap();
+        run_git(&work, &["commit", "--quiet", "-am", "three"]);
+        run_git(&work, &["tag", "v3"]);
+        run_git(&work, &["push", "--quiet", "--tags", &bare.to_string_lossy(), "main"]);
+
+        source.reference = Some("v3".to_owned());
+        let v3 = resolve(&source, cache.path()).unwrap();
+        assert_eq!(fs::read_to_string(v3.root.join("content/version")).unwrap(), "3");
+    }
+
+    #[test]
+    fn follows_branches_that_moved() {
+        let (dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("main".to_owned()),
+            ..Default::default()
+        };
+        let before = resolve(&source, cache.path()).unwrap();
+
+        let work = dir.path().join("work");
+        fs::write(work.join("content/version"), "3").unwrap();
+        run_git(&work, &["commit", "--quiet", "-am", "three"]);
+        run_git(&work, &["push", "--quiet", &bare.to_string_lossy(), "main"]);
+
+        let after = resolve(&source, cache.path()).unwrap();
+        assert_ne!(after.version, before.version);
+        assert_eq!(fs::read_to_string(after.root.join("content/version")).unwrap(), "3");
+    }
+
+    #[test]
+    fn checks_out_deleted_checkouts_again() {
+        let (_dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("v1".to_owned()),
+            ..Default::default()
+        };
+        let v1 = resolve(&source, cache.path()).unwrap();
+        fs::remove_dir_all(&v1.root).unwrap();
+
+        assert_eq!(resolve(&source, cache.path()).unwrap(), v1);
+        assert_eq!(fs::read_to_string(v1.root.join("content/version")).unwrap(), "1");
+    }
+
+    #[test]
+    fn rejects_refs_that_look_like_options() {
+        let (_dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("--upload-pack=touch".to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(resolve(&source, cache.path()).unwrap_err(), SkelError::GitError(_)));
+    }
+
+    fn write_tar_gz(path: &Path, prefix: &str, version: &str) {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            fs::File::create(path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(version.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, format!("{}content/version", prefix), version.as_bytes())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn write_zip(path: &Path, version: &str) {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        zip.start_file("content/version", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(version.as_bytes()).unwrap();
+        zip.finish().unwrap();
+    }
+
+    fn archive_source(path: &Path) -> SkeletonSource {
+        SkeletonSource {
+            archive: Some(path.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extracts_archives() {
+        let dir = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        write_tar_gz(&dir.path().join("skeleton.tar.gz"), "", "1");
+        write_zip(&dir.path().join("skeleton.zip"), "2");
+
+        let tar_gz = resolve(&archive_source(&dir.path().join("skeleton.tar.gz")), cache.path()).unwrap().root;
+        assert_eq!(fs::read_to_string(tar_gz.join("content/version")).unwrap(), "1");
+
+        let zip = resolve(&archive_source(&dir.path().join("skeleton.zip")), cache.path()).unwrap().root;
+        assert_eq!(fs::read_to_string(zip.join("content/version")).unwrap(), "2");
+    }
+
+    #[test]
+    fn extracts_archives_by_content() {
+        let dir = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let archive = dir.path().join("skeleton.tgz");
+
+        write_tar_gz(&archive, "", "1");
+        let first = resolve(&archive_source(&archive), cache.path()).unwrap();
+        assert_eq!(resolve(&archive_source(&archive), cache.path()).unwrap(), first);
+
+        write_tar_gz(&archive, "", "2");
+        let second = resolve(&archive_source(&archive), cache.path()).unwrap();
+        assert_ne!(second.version, first.version);
+        assert_eq!(fs::read_to_string(second.root.join("content/version")).unwrap(), "2");
+    }
+
+    #[test]
+    fn uses_a_single_top_level_directory() {
+        let dir = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let archive = dir.path().join("skeleton-1.0.tar.gz");
+        write_tar_gz(&archive, "skeleton-1.0/", "1");
+
+        let root = resolve(&archive_source(&archive), cache.path()).unwrap().root;
+        assert!(root.ends_with("skeleton-1.0"));
+        assert_eq!(fs::read_to_string(root.join("content/version")).unwrap(), "1");
+    }
+
+    #[test]
+    fn resolves_locked_versions() {
+        let (dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("main".to_owned()),
+            ..Default::default()
+        };
+        let locked = resolve(&source, cache.path()).unwrap();
+
+        let work = dir.path().join("work");
+        fs::write(work.join("content/version"), "3").unwrap();
+        run_git(&work, &["commit", "--quiet", "-am", "three"]);
+        run_git(&work, &["push", "--quiet", &bare.to_string_lossy(), "main"]);
+
+        let resolved = resolve_locked(&source, locked.version.as_deref(), cache.path()).unwrap();
+        assert_eq!(resolved, locked);
+
+        let archive = dir.path().join("skeleton.zip");
+        write_zip(&archive, "1");
+        let locked = resolve(&archive_source(&archive), cache.path()).unwrap();
+        write_zip(&archive, "2");
+
+        let resolved = resolve_locked(&archive_source(&archive), locked.version.as_deref(), cache.path()).unwrap();
+        assert_eq!(fs::read_to_string(resolved.root.join("content/version")).unwrap(), "1");
+
+        let result = resolve_locked(&archive_source(&archive), Some("0000"), cache.path());
+        assert!(matches!(result.unwrap_err(), SkelError::ArchiveError(_, _)));
+    }
+
+    #[test]
+    fn errors_for_invalid_archives() {
+        let dir = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let archive = dir.path().join("skeleton.zip");
+        fs::write(&archive, "not a zip").unwrap();
+
+        let result = resolve(&archive_source(&archive), cache.path());
+        assert!(matches!(result.unwrap_err(), SkelError::ArchiveError(path, _) if path == archive));
+        assert_eq!(fs::read_dir(cache.path().join("archive")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn errors_for_unknown_refs() {
+        let (_dir, bare) = bare_repository();
+        let cache = TempDir::new().unwrap();
+
+        let source = SkeletonSource {
+            repository: Some(bare.to_string_lossy().into_owned()),
+            reference: Some("v9".to_owned()),
+            ..Default::default()
+        };
+        assert!(matches!(resolve(&source, cache.path()).unwrap_err(), SkelError::GitError(_)));
+    }
+}
@@ -0,0 +1,24 @@
+use std::fs;
+
+use tempfile::TempDir;
+
+use crate::skeleton::Skeleton;
+
+/// A project whose default skeleton has the given content files, as
+/// `(path, data)` pairs relative to the content directory.
+pub fn project_with_content(files: &[(&str, &str)]) -> TempDir {
+    let project = TempDir::new().unwrap();
+    let content_root = project.path().join(".skeleton/content");
+    for (path, data) in files {
+        let source = content_root.join(path);
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(source, data).unwrap();
+    }
+
+    project
+}
+
+/// Loads the skeleton of a project made by `project_with_content`.
+pub fn load(project: &TempDir) -> Skeleton {
+    Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap()
+}
@@ -0,0 +1,322 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::SkelError;
+use crate::util::set_file_mode;
+
+/// A set of file changes that either all happen or none do. Writes are
+/// staged into hidden files next to their destination, so that committing
+/// only links and renames files within the same directory, and each file
+/// is replaced in one rename. If any of that fails, every file already
+/// changed is put back the way it was.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    changes: Vec<Change>,
+    /// Directories created while staging, outermost first
+    created_dirs: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+enum Change {
+    Write { destination: PathBuf, staged: PathBuf },
+    Delete { destination: PathBuf },
+}
+
+impl Change {
+    fn destination(&self) -> &Path {
+        match self {
+            Change::Write { destination, .. } | Change::Delete { destination } => destination,
+        }
+    }
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Stages `data` to be written to `destination` on commit, with `mode`
+    /// as its permissions if given.
+    pub fn write(&mut self, destination: &Path, data: &[u8], mode: Option<u32>) -> Result<(), SkelError> {
+        if let Some(parent) = destination.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        let staged = sibling(destination, "new");
+        fs::write(&staged, data)?;
+        if let Some(mode) = mode {
+            set_file_mode(&staged, mode)?;
+        }
+        self.changes.push(Change::Write {
+            destination: destination.to_owned(),
+            staged,
+        });
+
+        Ok(())
+    }
+
+    /// Stages a symlink to `target` to be created at `destination` on commit.
+    pub fn symlink(&mut self, destination: &Path, target: &str) -> Result<(), SkelError> {
+        if let Some(parent) = destination.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        let staged = sibling(destination, "new");
+        // unlike a write, creating a link won't replace a leftover one
+        let _ = fs::remove_file(&staged);
+        symlink(target, &staged)?;
+        self.changes.push(Change::Write {
+            destination: destination.to_owned(),
+            staged,
+        });
+
+        Ok(())
+    }
+
+    /// Stages `destination` to be deleted on commit.
+    pub fn delete(&mut self, destination: &Path) {
+        self.changes.push(Change::Delete {
+            destination: destination.to_owned(),
+        });
+    }
+
+    /// Moves every staged change into place, in the order they were staged.
+    /// Existing files are kept next to their destination until everything
+    /// else succeeded, so they can be restored if something fails.
+    pub fn commit(mut self) -> Result<(), SkelError> {
+        let mut committed: Vec<(&Change, Option<PathBuf>)> = Vec::new();
+
+        for change in &self.changes {
+            match commit_change(change) {
+                Ok(backup) => committed.push((change, backup)),
+                Err(err) => {
+                    for (change, backup) in committed.into_iter().rev() {
+                        rollback_change(change, backup);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        for (_, backup) in committed {
+            if let Some(backup) = backup {
+                let _ = fs::remove_file(backup);
+            }
+        }
+
+        // nothing is left to clean up
+        self.changes.clear();
+        self.created_dirs.clear();
+
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, dir: &Path) -> Result<(), SkelError> {
+        let mut missing: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        missing.reverse();
+
+        fs::create_dir_all(dir)?;
+        self.created_dirs.extend(missing);
+
+        Ok(())
+    }
+}
+
+/// Anything staged but not committed is removed, along with the directories
+/// that were created for it.
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        for change in &self.changes {
+            if let Change::Write { staged, .. } = change {
+                let _ = fs::remove_file(staged);
+            }
+        }
+
+        for dir in self.created_dirs.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Returns where the previous file was kept, if there was one.
+fn commit_change(change: &Change) -> Result<Option<PathBuf>, SkelError> {
+    let destination = change.destination();
+    match fs::symlink_metadata(destination) {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            if let Change::Write { staged, .. } = change {
+                fs::rename(staged, destination)?;
+            }
+            return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let backup = sibling(destination, "old");
+    // left behind by an earlier commit that was interrupted
+    let _ = fs::remove_file(&backup);
+
+    match change {
+        Change::Write { staged, .. } => {
+            // the old file stays reachable through a second link, so the new
+            // one can replace it in a single rename and the destination is
+            // never missing
+            fs::hard_link(destination, &backup).or_else(|_| fs::copy(destination, &backup).map(|_| ()))?;
+            if let Err(err) = fs::rename(staged, destination) {
+                let _ = fs::remove_file(&backup);
+                return Err(err.into());
+            }
+        }
+        Change::Delete { .. } => fs::rename(destination, &backup)?,
+    }
+
+    Ok(Some(backup))
+}
+
+fn rollback_change(change: &Change, backup: Option<PathBuf>) {
+    let destination = change.destination();
+    match backup {
+        Some(backup) => {
+            let _ = fs::rename(backup, destination);
+        }
+        None => {
+            if let Change::Write { .. } = change {
+                let _ = fs::remove_file(destination);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &str, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// A hidden file next to `path`, so renames never cross filesystems.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.skel-{}", file_name, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn commits_writes_and_deletes() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("changed"), "old").unwrap();
+        fs::write(dir.path().join("deleted"), "old").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.write(&dir.path().join("changed"), b"new", None).unwrap();
+        transaction.write(&dir.path().join("nested/created"), b"new", None).unwrap();
+        transaction.delete(&dir.path().join("deleted"));
+
+        // nothing changes until commit
+        assert_eq!(fs::read_to_string(dir.path().join("changed")).unwrap(), "old");
+        assert!(!dir.path().join("nested/created").exists());
+
+        transaction.commit().unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("changed")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.path().join("nested/created")).unwrap(), "new");
+        assert_eq!(files(dir.path()), vec!["changed", "nested"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn writes_with_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("script.sh"), "old").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.write(&dir.path().join("script.sh"), b"new", Some(0o755)).unwrap();
+        transaction.commit().unwrap();
+
+        let metadata = fs::metadata(dir.path().join("script.sh")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+    }
+
+    #[test]
+    fn commits_symlinks() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("link"), "old").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.symlink(&dir.path().join("link"), "../target").unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(fs::read_link(dir.path().join("link")).unwrap(), PathBuf::from("../target"));
+        assert_eq!(files(dir.path()), vec!["link"]);
+    }
+
+    #[test]
+    fn replaces_leftovers_from_an_interrupted_commit() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("changed"), "old").unwrap();
+        fs::write(sibling(&dir.path().join("changed"), "old"), "older").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.write(&dir.path().join("changed"), b"new", None).unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(fs::read_to_string(dir.path().join("changed")).unwrap(), "new");
+        assert_eq!(files(dir.path()), vec!["changed"]);
+    }
+
+    #[test]
+    fn cleans_up_when_dropped() {
+        let dir = TempDir::new().unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.write(&dir.path().join("one"), b"one", None).unwrap();
+        transaction.write(&dir.path().join("nested/deeper/two"), b"two", None).unwrap();
+        drop(transaction);
+
+        assert_eq!(files(dir.path()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn rolls_back_when_a_change_fails() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("one"), "old").unwrap();
+        fs::write(dir.path().join("two"), "old").unwrap();
+        fs::write(dir.path().join("three"), "old").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.write(&dir.path().join("one"), b"new", None).unwrap();
+        transaction.delete(&dir.path().join("two"));
+        transaction.write(&dir.path().join("nested/created"), b"new", None).unwrap();
+        transaction.write(&dir.path().join("three"), b"new", None).unwrap();
+        // losing a staged file makes its rename fail
+        fs::remove_file(sibling(&dir.path().join("three"), "new")).unwrap();
+
+        assert!(transaction.commit().is_err());
+        assert_eq!(fs::read_to_string(dir.path().join("one")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(dir.path().join("two")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(dir.path().join("three")).unwrap(), "old");
+        assert_eq!(files(dir.path()), vec!["one", "three", "two"]);
+    }
+}
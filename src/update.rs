@@ -0,0 +1,346 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::apply::lock;
+use crate::config::Lockfile;
+use crate::content::{ContentKind, Policy};
+use crate::error::SkelError;
+use crate::history::commit_with_history;
+use crate::merge::merge;
+use crate::plan::{Action, Existing, Plan, Step};
+use crate::skeleton::Skeleton;
+use crate::source;
+use crate::transaction::Transaction;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UpdateResult {
+    pub destination: PathBuf,
+    pub status: UpdateStatus,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UpdateStatus {
+    Created,
+    Updated,
+    Merged,
+    Conflict,
+    Unchanged,
+}
+
+impl fmt::Display for UpdateStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            UpdateStatus::Created => "created",
+            UpdateStatus::Updated => "updated",
+            UpdateStatus::Merged => "merged",
+            UpdateStatus::Conflict => "conflict",
+            UpdateStatus::Unchanged => "unchanged",
+        };
+
+        f.pad(status)
+    }
+}
+
+/// Brings the project up to date with the skeleton while keeping changes
+/// made to it since the last apply. Each file is merged three ways: the old
+/// rendering, as kept by the last apply or else rebuilt from the lockfile,
+/// is the base, the project's file is one side and the new rendering is the
+/// other. Conflicting lines are left
+/// between markers for the project to resolve. Like apply, nothing is
+/// changed unless every file can be written, and the files it replaces are
+/// kept in the history.
+pub fn update(skeleton: &Skeleton) -> Result<Vec<UpdateResult>, SkelError> {
+    let lockfile = match Lockfile::read_from(&skeleton.lockfile, &skeleton.project)? {
+        Some(lockfile) => lockfile,
+        None => return Err(SkelError::MissingLockfile(skeleton.lockfile.clone())),
+    };
+
+    let mut bases = stored_bases(&skeleton.project, &lockfile)?;
+    if lockfile.content.keys().any(|destination| !bases.contains_key(destination)) {
+        // lockfiles from before bases were kept need the old rendering
+        // rebuilt, which only works if the skeleton is versioned
+        let previous = Skeleton::from_lockfile(skeleton.project.clone(), &lockfile)?;
+        for step in Plan::from_skeleton(&previous)?.steps {
+            if !bases.contains_key(&step.destination)
+                && lockfile.content.get(&step.destination) == Some(&source::hash(&step.data))
+            {
+                bases.insert(step.destination, step.data);
+            }
+        }
+    }
+
+    let plan = Plan::from_skeleton(skeleton)?;
+    let mut transaction = Transaction::new();
+    let mut changed: Vec<PathBuf> = Vec::new();
+    let mut results: Vec<UpdateResult> = Vec::new();
+
+    for step in &plan.steps {
+        // files dropped from the skeleton are left for apply --prune
+        if matches!(step.action, Action::Delete | Action::Orphan | Action::Modified) {
+            continue;
+        }
+
+        let destination = skeleton.project.join(&step.destination);
+        let (status, data) = match Existing::read(&destination)? {
+            None => (UpdateStatus::Created, Some(step.data.clone())),
+            Some(_) if step.action == Action::Chmod => (UpdateStatus::Updated, Some(step.data.clone())),
+            Some(current) if current.matches(&step.kind, &step.data) => (UpdateStatus::Unchanged, None),
+            Some(_) if step.policy == Policy::CreateOnly => (UpdateStatus::Unchanged, None),
+            Some(current) if lockfile.content.get(&step.destination) == Some(&source::hash(current.data())) => {
+                (UpdateStatus::Updated, Some(step.data.clone()))
+            }
+            Some(Existing::File(current)) if step.kind != ContentKind::Symlink => {
+                let base = bases.get(&step.destination).map(Vec::as_slice).unwrap_or_default();
+                match (std::str::from_utf8(base), std::str::from_utf8(&current), std::str::from_utf8(&step.data)) {
+                    (Ok(base), Ok(ours), Ok(theirs)) => {
+                        let merged = merge(base, ours, theirs);
+                        let status = match merged.conflicts {
+                            0 => UpdateStatus::Merged,
+                            _ => UpdateStatus::Conflict,
+                        };
+                        (status, Some(merged.text.into_bytes()))
+                    }
+                    // binary files can't be merged, so the project's copy is kept
+                    _ => (UpdateStatus::Conflict, None),
+                }
+            }
+            // neither can symlinks
+            Some(_) => (UpdateStatus::Conflict, None),
+        };
+
+        if let Some(data) = data {
+            match step.kind {
+                ContentKind::Symlink => transaction.symlink(&destination, &String::from_utf8_lossy(&data))?,
+                _ => transaction.write(&destination, &data, step.mode)?,
+            }
+            changed.push(step.destination.clone());
+        }
+
+        results.push(UpdateResult {
+            destination: step.destination.clone(),
+            status,
+        });
+    }
+
+    let lockfile = lock(skeleton, &plan, Some(&lockfile));
+    stage_bases(&skeleton.project, &mut transaction, &plan.steps)?;
+    transaction.write(&skeleton.lockfile, lockfile.to_kdl_string(&skeleton.project).as_bytes(), None)?;
+    if !changed.is_empty() {
+        if let Ok(lockfile) = skeleton.lockfile.strip_prefix(&skeleton.project) {
+            changed.push(lockfile.to_owned());
+        }
+    }
+    commit_with_history(&skeleton.project, transaction, &changed)?;
+    prune_bases(&skeleton.project, &lockfile)?;
+
+    Ok(results)
+}
+
+/// Where what the skeleton rendered into each managed file is kept, named
+/// by its hash, to merge against later.
+pub fn bases_dir(project: &Path) -> PathBuf {
+    project.join(".skeleton/bases")
+}
+
+/// Stages a copy of what each step renders unless one is kept already. With
+/// these, update can merge against what was written even when the skeleton
+/// can't be rebuilt as it was, like a local skeleton that changed since.
+pub(crate) fn stage_bases(project: &Path, transaction: &mut Transaction, steps: &[Step]) -> Result<(), SkelError> {
+    let mut staged: HashSet<String> = HashSet::new();
+    for step in steps {
+        if step.policy == Policy::CreateOnly || matches!(step.action, Action::Delete | Action::Orphan | Action::Modified) {
+            continue;
+        }
+
+        let hash = source::hash(&step.data);
+        let base = bases_dir(project).join(&hash);
+        if !base.exists() && staged.insert(hash) {
+            transaction.write(&base, &step.data, None)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the kept renderings that `lockfile` no longer refers to.
+pub(crate) fn prune_bases(project: &Path, lockfile: &Lockfile) -> Result<(), SkelError> {
+    let entries = match fs::read_dir(bases_dir(project)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let hashes: HashSet<&String> = lockfile.content.values().collect();
+    for entry in entries {
+        let entry = entry?;
+        if !hashes.contains(&entry.file_name().to_string_lossy().into_owned()) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The kept rendering of every file in `lockfile`, for those that have one.
+/// A copy that doesn't match its hash, say from an interrupted write, is
+/// left out.
+fn stored_bases(project: &Path, lockfile: &Lockfile) -> Result<HashMap<PathBuf, Vec<u8>>, SkelError> {
+    let mut bases: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+    for (destination, hash) in &lockfile.content {
+        let data = match fs::read(bases_dir(project).join(hash)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if source::hash(&data) == *hash {
+            bases.insert(destination.clone(), data);
+        }
+    }
+
+    Ok(bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    use crate::apply::apply;
+    use crate::test_helpers::{load, project_with_content};
+
+    #[test]
+    fn errors_without_a_lockfile() {
+        let project = project_with_content(&[("README.md", "one\n")]);
+
+        let result = update(&load(&project));
+        assert!(matches!(result.unwrap_err(), SkelError::MissingLockfile(_)));
+    }
+
+    #[test]
+    fn updates_files_the_project_did_not_change() {
+        let project = project_with_content(&[("README.md", "one\n")]);
+        apply(&load(&project)).unwrap();
+
+        fs::write(project.path().join(".skeleton/content/README.md"), "two\n").unwrap();
+        let results = update(&load(&project)).unwrap();
+        assert_eq!(results, vec![
+            UpdateResult { destination: PathBuf::from("README.md"), status: UpdateStatus::Updated },
+        ]);
+        assert_eq!(fs::read_to_string(project.path().join("README.md")).unwrap(), "two\n");
+    }
+
+    fn project_with_template(name: &str) -> TempDir {
+        let project = project_with_content(&[("README.md", "# {{ name }}\n\nabout\n\nlicense\n")]);
+        fs::write(project.path().join(".skeleton/skeleton.kdl"), r#"
+            content "README.md" {
+                kind "template"
+            }
+        "#).unwrap();
+        set_name(&project, name);
+
+        project
+    }
+
+    fn set_name(project: &TempDir, name: &str) {
+        fs::write(project.path().join(".skeleton.kdl"), format!(r#"
+            variables {{
+                name "{}"
+            }}
+        "#, name)).unwrap();
+    }
+
+    #[test]
+    fn leaves_create_only_content_alone() {
+        let project = project_with_content(&[("README.md", "one\n")]);
+        fs::write(project.path().join(".skeleton/skeleton.kdl"), r#"
+            content "README.md" {
+                policy "create_only"
+            }
+        "#).unwrap();
+        apply(&load(&project)).unwrap();
+
+        fs::write(project.path().join(".skeleton/content/README.md"), "two\n").unwrap();
+        let results = update(&load(&project)).unwrap();
+        assert_eq!(results, vec![
+            UpdateResult { destination: PathBuf::from("README.md"), status: UpdateStatus::Unchanged },
+        ]);
+        assert_eq!(fs::read_to_string(project.path().join("README.md")).unwrap(), "one\n");
+    }
+
+    #[test]
+    fn merges_skeleton_changes_into_project_changes() {
+        let project = project_with_template("skel");
+        apply(&load(&project)).unwrap();
+
+        fs::write(project.path().join("README.md"), "# skel\n\nabout skel\n\nlicense\n").unwrap();
+        set_name(&project, "bones");
+
+        let results = update(&load(&project)).unwrap();
+        assert_eq!(results, vec![
+            UpdateResult { destination: PathBuf::from("README.md"), status: UpdateStatus::Merged },
+        ]);
+        assert_eq!(
+            fs::read_to_string(project.path().join("README.md")).unwrap(),
+            "# bones\n\nabout skel\n\nlicense\n"
+        );
+    }
+
+    #[test]
+    fn merges_changes_to_a_local_skeleton() {
+        let project = project_with_content(&[("README.md", "one\ntwo\nthree\nfour\nfive\n")]);
+        apply(&load(&project)).unwrap();
+
+        fs::write(project.path().join("README.md"), "ONE\ntwo\nthree\nfour\nfive\n").unwrap();
+        fs::write(project.path().join(".skeleton/content/README.md"), "one\ntwo\nthree\nfour\nFIVE\n").unwrap();
+
+        let results = update(&load(&project)).unwrap();
+        assert_eq!(results, vec![
+            UpdateResult { destination: PathBuf::from("README.md"), status: UpdateStatus::Merged },
+        ]);
+        assert_eq!(fs::read_to_string(project.path().join("README.md")).unwrap(), "ONE\ntwo\nthree\nfour\nFIVE\n");
+
+        // only the rendering the lockfile now refers to is kept
+        let bases: Vec<PathBuf> = fs::read_dir(bases_dir(project.path())).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(bases, vec![bases_dir(project.path()).join(source::hash(b"one\ntwo\nthree\nfour\nFIVE\n"))]);
+    }
+
+    #[test]
+    fn marks_conflicts() {
+        let project = project_with_template("skel");
+        apply(&load(&project)).unwrap();
+
+        fs::write(project.path().join("README.md"), "# my skel\n\nabout\n\nlicense\n").unwrap();
+        set_name(&project, "bones");
+
+        let results = update(&load(&project)).unwrap();
+        assert_eq!(results, vec![
+            UpdateResult { destination: PathBuf::from("README.md"), status: UpdateStatus::Conflict },
+        ]);
+        assert_eq!(
+            fs::read_to_string(project.path().join("README.md")).unwrap(),
+            "<<<<<<< project\n# my skel\n=======\n# bones\n>>>>>>> skeleton\n\nabout\n\nlicense\n"
+        );
+    }
+
+    #[test]
+    fn records_the_new_rendering() {
+        let project = project_with_template("skel");
+        apply(&load(&project)).unwrap();
+
+        fs::write(project.path().join("README.md"), "# skel\n\nabout skel\n\nlicense\n").unwrap();
+        set_name(&project, "bones");
+        update(&load(&project)).unwrap();
+
+        let lockfile = Lockfile::read_from(&project.path().join(".skeleton.lock"), project.path()).unwrap().unwrap();
+        assert_eq!(lockfile.variables.get("name").unwrap(), "bones");
+        assert_eq!(
+            lockfile.content.get(&PathBuf::from("README.md")),
+            Some(&source::hash(b"# bones\n\nabout\n\nlicense\n"))
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::fs;
+use std::io;
 use std::path::{Component, Path, PathBuf};
 use crate::error::SkelError;
 
@@ -38,6 +40,50 @@ where
     Ok(PathBuf::from(result.join("/")))
 }
 
+/// Picks the candidate closest to a misspelled input, if any is close enough
+/// to be worth suggesting.
+pub fn did_you_mean<'a>(input: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let input = input.trim().to_lowercase();
+
+    candidates
+        .iter()
+        .map(|candidate| (candidate, strsim::levenshtein(&input, candidate)))
+        .filter(|(candidate, distance)| *distance <= 2 || *distance < candidate.len() / 2)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| *candidate)
+}
+
+/// The permission bits of the file at `path`, or None if there is no file
+/// there or the platform has no such thing. Symlinks aren't followed.
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_symlink() => Ok(None),
+        Ok(metadata) => Ok(Some(metadata.permissions().mode() & 0o7777)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+pub fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_file_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +116,19 @@ mod tests {
             assert_eq!(result, from.join("test"));
         }
     }
+
+    mod did_you_mean {
+        use super::*;
+
+        #[test]
+        fn suggests_the_closest_candidate() {
+            assert_eq!(did_you_mean("tempalte", &["file", "template"]), Some("template"));
+            assert_eq!(did_you_mean("Fiel", &["file", "template"]), Some("file"));
+        }
+
+        #[test]
+        fn ignores_distant_candidates() {
+            assert_eq!(did_you_mean("directory", &["file", "template"]), None);
+        }
+    }
 }
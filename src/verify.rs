@@ -0,0 +1,164 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::error::SkelError;
+use crate::content::Policy;
+use crate::plan::{Action, Plan};
+use crate::skeleton::Skeleton;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Drift {
+    pub destination: PathBuf,
+    pub kind: DriftKind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DriftKind {
+    Missing,
+    Modified,
+    Orphaned,
+    Permissions,
+}
+
+impl fmt::Display for DriftKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            DriftKind::Missing => "missing",
+            DriftKind::Modified => "modified",
+            DriftKind::Orphaned => "orphaned",
+            DriftKind::Permissions => "permissions",
+        };
+
+        f.pad(kind)
+    }
+}
+
+/// Compares every piece of content against the project and returns the
+/// destinations that no longer match, along with files the skeleton no
+/// longer produces. An empty result means no drift.
+pub fn verify(skeleton: &Skeleton) -> Result<Vec<Drift>, SkelError> {
+    let plan = Plan::from_skeleton(skeleton)?;
+    let mut drift: Vec<Drift> = Vec::new();
+
+    for step in plan.steps {
+        // once created, create-only content belongs to the project
+        if step.policy == Policy::CreateOnly {
+            continue;
+        }
+
+        let kind = match step.action {
+            Action::Create => DriftKind::Missing,
+            Action::Overwrite => DriftKind::Modified,
+            Action::Delete | Action::Orphan | Action::Modified => DriftKind::Orphaned,
+            Action::Chmod => DriftKind::Permissions,
+            Action::Skip => continue,
+        };
+
+        drift.push(Drift {
+            destination: step.destination,
+            kind,
+        });
+    }
+
+    Ok(drift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::apply::apply;
+    use crate::test_helpers::project_with_content;
+
+    #[test]
+    fn reports_no_drift_after_apply() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two")]);
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        apply(&skeleton).unwrap();
+
+        assert_eq!(verify(&skeleton).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn reports_missing_and_modified_files() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two"), ("three.txt", "three")]);
+        fs::write(project.path().join("two.txt"), "changed").unwrap();
+        fs::write(project.path().join("three.txt"), "three").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![
+            Drift { destination: PathBuf::from("one.txt"), kind: DriftKind::Missing },
+            Drift { destination: PathBuf::from("two.txt"), kind: DriftKind::Modified },
+        ]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn checks_symlink_targets() {
+        let project = project_with_content(&[("symlink_config", "shared/config")]);
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        apply(&skeleton).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![]);
+
+        // a file with the target as its contents is still not the link
+        fs::remove_file(project.path().join("config")).unwrap();
+        fs::write(project.path().join("config"), "shared/config").unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![
+            Drift { destination: PathBuf::from("config"), kind: DriftKind::Modified },
+        ]);
+
+        fs::remove_file(project.path().join("config")).unwrap();
+        std::os::unix::fs::symlink("elsewhere", project.path().join("config")).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![
+            Drift { destination: PathBuf::from("config"), kind: DriftKind::Modified },
+        ]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reports_permission_drift() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let project = project_with_content(&[("executable_build.sh", "build")]);
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        apply(&skeleton).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![]);
+
+        fs::set_permissions(project.path().join("build.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![
+            Drift { destination: PathBuf::from("build.sh"), kind: DriftKind::Permissions },
+        ]);
+    }
+
+    #[test]
+    fn reports_orphaned_files() {
+        let project = project_with_content(&[("one.txt", "one"), ("two.txt", "two")]);
+        apply(&Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap()).unwrap();
+        fs::remove_file(project.path().join(".skeleton/content/two.txt")).unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![
+            Drift { destination: PathBuf::from("two.txt"), kind: DriftKind::Orphaned },
+        ]);
+    }
+
+    #[test]
+    fn ignores_create_only_content() {
+        let project = project_with_content(&[("README.md", "readme"), ("LICENSE", "license")]);
+        fs::write(project.path().join(".skeleton/skeleton.kdl"), r#"
+            content "README.md" {
+                policy "create_only"
+            }
+        "#).unwrap();
+        fs::write(project.path().join("LICENSE"), "changed").unwrap();
+
+        let skeleton = Skeleton::from_config_file(project.path().join(".skeleton.kdl")).unwrap();
+        assert_eq!(verify(&skeleton).unwrap(), vec![
+            Drift { destination: PathBuf::from("LICENSE"), kind: DriftKind::Modified },
+        ]);
+
+        fs::write(project.path().join("README.md"), "changed").unwrap();
+        assert_eq!(verify(&skeleton).unwrap().len(), 1);
+    }
+}